@@ -0,0 +1,235 @@
+//! Access control for the server.
+//!
+//! An [`AccessPolicy`] admits clients by source address and user-agent.
+//! Deny lists take precedence, a non-empty allow list admits only the
+//! clients it matches. An empty policy admits everyone, like a console.
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use crate::error::Error;
+
+/// Network in CIDR notation, a plain address is a single host network
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self, Error> {
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix > max {
+            return Err(Error::GeneralError(format!("Invalid prefix length /{prefix} for {addr}")));
+        }
+
+        Ok(Self { addr, prefix })
+    }
+
+    /// Whether `ip` lies in the network, IPv4-mapped IPv6 addresses count as IPv4
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            v4 => v4,
+        };
+
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| Error::GeneralError(format!("Invalid address: {s}")))?;
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .map_err(|_| Error::GeneralError(format!("Invalid prefix length: {s}")))?,
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+
+        Self::new(addr, prefix)
+    }
+}
+
+/// Why a client was turned away
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Denied {
+    /// Source address matches the deny list
+    AddressDenied(Cidr),
+    /// Source address matches no entry of the allow list
+    AddressNotAllowed,
+    /// User-agent matches the deny list
+    UserAgentDenied(String),
+    /// User-agent, if any, matches no entry of the allow list
+    UserAgentNotAllowed,
+}
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AddressDenied(cidr) => write!(f, "address denied by {cidr}"),
+            Self::AddressNotAllowed => write!(f, "address not allowed"),
+            Self::UserAgentDenied(pattern) => write!(f, "user-agent denied by {pattern:?}"),
+            Self::UserAgentNotAllowed => write!(f, "user-agent not allowed"),
+        }
+    }
+}
+
+/// Allow and deny lists applied to every request
+///
+/// User-agents match case-insensitively on a substring, so `CopyOnLanSvc`
+/// matches the versioned agent consoles send.
+#[derive(Debug, Clone, Default)]
+pub struct AccessPolicy {
+    allow_addresses: Vec<Cidr>,
+    deny_addresses: Vec<Cidr>,
+    allow_user_agents: Vec<String>,
+    deny_user_agents: Vec<String>,
+}
+
+impl AccessPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Policy of the given allow and deny lists, as taken on the command line
+    pub fn from_lists(allow: &[Cidr], deny: &[Cidr], allow_user_agents: &[String], deny_user_agents: &[String]) -> Self {
+        Self {
+            allow_addresses: allow.to_vec(),
+            deny_addresses: deny.to_vec(),
+            allow_user_agents: allow_user_agents.iter().map(|pattern| pattern.to_lowercase()).collect(),
+            deny_user_agents: deny_user_agents.iter().map(|pattern| pattern.to_lowercase()).collect(),
+        }
+    }
+
+    /// Admit only clients in the allowed networks
+    pub fn allow_address(mut self, cidr: Cidr) -> Self {
+        self.allow_addresses.push(cidr);
+        self
+    }
+
+    pub fn deny_address(mut self, cidr: Cidr) -> Self {
+        self.deny_addresses.push(cidr);
+        self
+    }
+
+    /// Admit only clients sending an allowed user-agent
+    pub fn allow_user_agent(mut self, pattern: &str) -> Self {
+        self.allow_user_agents.push(pattern.to_lowercase());
+        self
+    }
+
+    pub fn deny_user_agent(mut self, pattern: &str) -> Self {
+        self.deny_user_agents.push(pattern.to_lowercase());
+        self
+    }
+
+    /// Whether the policy admits everyone
+    pub fn is_open(&self) -> bool {
+        self.allow_addresses.is_empty()
+            && self.deny_addresses.is_empty()
+            && self.allow_user_agents.is_empty()
+            && self.deny_user_agents.is_empty()
+    }
+
+    /// Admit or deny a request from `ip` sending `user_agent`
+    pub fn check(&self, ip: IpAddr, user_agent: Option<&str>) -> Result<(), Denied> {
+        if let Some(cidr) = self.deny_addresses.iter().find(|cidr| cidr.contains(ip)) {
+            return Err(Denied::AddressDenied(*cidr));
+        }
+        if !self.allow_addresses.is_empty() && !self.allow_addresses.iter().any(|cidr| cidr.contains(ip)) {
+            return Err(Denied::AddressNotAllowed);
+        }
+
+        let user_agent = user_agent.map(str::to_lowercase);
+        let matches = |pattern: &&String| match &user_agent {
+            Some(user_agent) => user_agent.contains(pattern.as_str()),
+            None => false,
+        };
+        if let Some(pattern) = self.deny_user_agents.iter().find(matches) {
+            return Err(Denied::UserAgentDenied(pattern.clone()));
+        }
+        if !self.allow_user_agents.is_empty() && !self.allow_user_agents.iter().any(|pattern| matches(&pattern)) {
+            return Err(Denied::UserAgentNotAllowed);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr() {
+        let lab: Cidr = "192.168.10.0/24".parse().unwrap();
+        assert_eq!(lab.to_string(), "192.168.10.0/24");
+        assert!(lab.contains(ip("192.168.10.77")));
+        assert!(lab.contains(ip("::ffff:192.168.10.1")));
+        assert!(!lab.contains(ip("192.168.11.1")));
+        assert!(!lab.contains(ip("fe80::1")));
+
+        let host: Cidr = "10.0.0.5".parse().unwrap();
+        assert!(host.contains(ip("10.0.0.5")));
+        assert!(!host.contains(ip("10.0.0.6")));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(ip("8.8.8.8")));
+        assert!("fd00::/8".parse::<Cidr>().unwrap().contains(ip("fd12::1")));
+
+        for invalid in ["10.0.0.0/33", "fd00::/129", "10.0.0/8", "10.0.0.0/x", ""] {
+            assert!(invalid.parse::<Cidr>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_policy() {
+        assert!(AccessPolicy::new().is_open());
+        assert_eq!(AccessPolicy::new().check(ip("1.2.3.4"), None), Ok(()));
+
+        let policy = AccessPolicy::new()
+            .allow_address("192.168.10.0/24".parse().unwrap())
+            .deny_address("192.168.10.13".parse().unwrap())
+            .allow_user_agent("CopyOnLanSvc")
+            .deny_user_agent("curl");
+
+        assert_eq!(policy.check(ip("192.168.10.2"), Some("copyonlansvc/1.0")), Ok(()));
+        assert_eq!(policy.check(ip("192.168.10.13"), Some("CopyOnLanSvc")), Err(Denied::AddressDenied("192.168.10.13/32".parse().unwrap())));
+        assert_eq!(policy.check(ip("192.168.20.2"), Some("CopyOnLanSvc")), Err(Denied::AddressNotAllowed));
+        assert_eq!(policy.check(ip("192.168.10.2"), Some("curl CopyOnLanSvc")), Err(Denied::UserAgentDenied("curl".into())));
+        assert_eq!(policy.check(ip("192.168.10.2"), None), Err(Denied::UserAgentNotAllowed));
+
+        let policy = AccessPolicy::from_lists(&["192.168.10.0/24".parse().unwrap()], &[], &["CopyOnLanSvc".into()], &[]);
+        assert_eq!(policy.check(ip("192.168.10.2"), Some("copyonlansvc/1.0")), Ok(()));
+        assert_eq!(policy.check(ip("192.168.10.2"), Some("curl")), Err(Denied::UserAgentNotAllowed));
+    }
+}
@@ -0,0 +1,166 @@
+//! Admin endpoints, served on a port separate from the console emulation.
+//!
+//! Consoles never see these routes. [`router`] is bound by [`crate::Server`]
+//! when an admin address is configured, on loopback unless a bearer token
+//! guards it. Files can be added from the library directory only.
+//!
+//! ```text
+//! GET    /metrics                Prometheus metrics
+//! GET    /items                  library, as listed in /col/metadata
+//! POST   /items {"file": ...}    offer a library file, split file or tar archive
+//! DELETE /items?path=...         stop offering an item, by metadata path
+//! GET    /transfers              content responses in flight
+//! GET    /console                advertised identity
+//! PUT    /console {"name": ...}  rename and re-announce
+//! ```
+use std::{path::PathBuf, sync::Arc};
+
+use axum::{
+    body::Body,
+    extract::{Json, Query, State},
+    http::{header, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, server::AppState, ContentPath};
+
+/// Content type of the Prometheus text format
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Admin endpoints, requiring `Authorization: Bearer <token>` if a token is given
+pub fn router(state: Arc<AppState>, token: Option<String>) -> Router {
+    let router = Router::new()
+        .route("/metrics", get(get_metrics))
+        .route("/items", get(list_items).post(add_item).delete(remove_item))
+        .route("/transfers", get(list_transfers))
+        .route("/console", get(get_console).put(rename_console))
+        .with_state(state);
+
+    match token {
+        Some(token) => router.layer(middleware::from_fn_with_state(Arc::<str>::from(token), require_token)),
+        None => router,
+    }
+}
+
+/// Reject requests without the bearer token with `401 Unauthorized`
+async fn require_token(State(token): State<Arc<str>>, request: Request<Body>, next: Next<Body>) -> Response {
+    let given = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match given.is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes())) {
+        true => next.run(request).await,
+        false => {
+            log::warn!("Unauthorized admin request: {} {}", request.method(), request.uri());
+            (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")]).into_response()
+        }
+    }
+}
+
+/// Compare without returning early, the time taken does not reveal the matching prefix
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[derive(Debug, Deserialize)]
+struct AddItem {
+    file: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct ItemQuery {
+    path: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Rename {
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+/// Error as JSON, with a status matching its kind
+fn error_response(e: Error) -> Response {
+    let (status, error) = match e {
+        Error::NotFound(path) => (StatusCode::NOT_FOUND, format!("Not found: {path}")),
+        Error::GeneralError(message) => (StatusCode::BAD_REQUEST, message),
+        Error::IoError(e) if e.kind() == std::io::ErrorKind::NotFound => (StatusCode::NOT_FOUND, e.to_string()),
+        e => (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:?}")),
+    };
+    log::warn!("Admin request failed: {error}");
+
+    (status, Json(ErrorBody { error })).into_response()
+}
+
+/// Metrics in Prometheus text format
+async fn get_metrics(State(state): State<Arc<AppState>>) -> Response {
+    ([(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)], state.metrics().render()).into_response()
+}
+
+async fn list_items(State(state): State<Arc<AppState>>) -> Response {
+    match state.provider().list().await {
+        Ok(metadata) => Json(metadata.items).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Offer a file of the library directory, `201 Created` with the items added
+async fn add_item(State(state): State<Arc<AppState>>, Json(request): Json<AddItem>) -> Response {
+    match state.provider().add_file(&request.file).await {
+        Ok(items) => {
+            log::info!("Added {:?}: {} item(s)", request.file, items.len());
+            (StatusCode::CREATED, Json(items)).into_response()
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+async fn remove_item(State(state): State<Arc<AppState>>, Query(query): Query<ItemQuery>) -> Response {
+    let removed = match ContentPath::from_url_path(&query.path) {
+        Ok(path) => state.provider().remove_item(&path).await,
+        Err(e) => Err(e),
+    };
+
+    match removed {
+        Ok(item) => {
+            log::info!("Removed {}", item.path);
+            Json(item).into_response()
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+async fn list_transfers(State(state): State<Arc<AppState>>) -> Response {
+    Json(state.metrics().transfers()).into_response()
+}
+
+async fn get_console(State(state): State<Arc<AppState>>) -> Response {
+    match state.console() {
+        Some(console) => Json(console).into_response(),
+        None => error_response(Error::NotFound("console".into())),
+    }
+}
+
+/// Rename the console, registering the new mDNS service before withdrawing the old
+async fn rename_console(State(state): State<Arc<AppState>>, Json(request): Json<Rename>) -> Response {
+    if request.name.trim().is_empty() {
+        return error_response(Error::GeneralError("Console name must not be empty".into()));
+    }
+
+    // Withdrawing the old announcement blocks while it is confirmed
+    let renamed = tokio::task::spawn_blocking(move || state.rename(request.name.trim())).await;
+    match renamed {
+        Ok(Ok(console)) => Json(console).into_response(),
+        Ok(Err(e)) => error_response(e),
+        Err(e) => error_response(Error::GeneralError(format!("Rename failed: {e}"))),
+    }
+}
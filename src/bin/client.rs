@@ -1,37 +1,162 @@
-use std::io::Seek;
+use std::{collections::HashSet, fs::OpenOptions, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::{Duration, Instant}};
 
 use anyhow::{Result, Context};
+use clap::{Parser, Subcommand};
 use env_logger::Env;
-use indicatif::{ProgressBar, ProgressStyle};
-use network_transfer::{models::MetadataItem, Client, NetworkTransferProtocol};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use network_transfer::{cache::{MetadataCache, MetadataDiff}, chunking::{parse_size, ChunkSizing}, models::MetadataItem, report::{write_inventory, InventoryRow, OutputFormat}, queue::{download_job, JobState, TransferQueue}, summary::TransferSummary, throttle::{parse_rate, RateLimiter}, Client, ClientBuilder, Console, NetworkTransferProtocol};
+use tokio_util::sync::CancellationToken;
 
-const STEP_SIZE: usize = 0x10000;
+#[derive(Parser, Debug)]
+#[command(about = "Download content from a console via network-transfer")]
+struct Args {
+    /// Limit each download to this many bytes per second (suffixes K, M, G)
+    #[arg(long, global = true, value_parser = parse_rate)]
+    rate_limit: Option<u64>,
+    /// Limit all concurrent downloads together to this many bytes per second (suffixes K, M, G)
+    #[arg(long, global = true, value_parser = parse_rate)]
+    global_rate_limit: Option<u64>,
+    /// Smallest chunk requested, also the initial chunk size (suffixes K, M, G)
+    #[arg(long, global = true, default_value = "64K", value_parser = parse_size)]
+    min_chunk_size: usize,
+    /// Largest chunk requested while throughput keeps improving (suffixes K, M, G)
+    #[arg(long, global = true, default_value = "16M", value_parser = parse_size)]
+    max_chunk_size: usize,
+    /// Timeout for connecting to the console, e.g. `5s`
+    #[arg(long, global = true, value_parser = humantime::parse_duration)]
+    connect_timeout: Option<Duration>,
+    /// Timeout for each socket read, e.g. `30s`
+    #[arg(long, global = true, value_parser = humantime::parse_duration)]
+    read_timeout: Option<Duration>,
+    /// Overall timeout of a single request, e.g. `2m`
+    #[arg(long, global = true, value_parser = humantime::parse_duration)]
+    timeout: Option<Duration>,
+    /// User agent sent with every request
+    #[arg(long, global = true, default_value = ClientBuilder::DEFAULT_USER_AGENT)]
+    user_agent: String,
+    /// Skip metadata items that fail to parse instead of failing the whole list
+    #[arg(long, global = true)]
+    lenient: bool,
+    /// Write the transfer summary as JSON to this file, a list when running the queue
+    #[arg(long, global = true, value_name = "FILE")]
+    summary_json: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-fn download_with_progress(client: &Client, item: &MetadataItem, writer: &mut (impl std::io::Write + std::io::Seek)) -> Result<usize> {
-    let content_length = client.get_item_filesize(item)?;
-    dbg!(content_length);
+/// Settings applied to every [`Client`] created
+struct ClientOptions {
+    per_client: Option<u64>,
+    global: Option<Arc<RateLimiter>>,
+    sizing: ChunkSizing,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    timeout: Option<Duration>,
+    user_agent: String,
+    lenient: bool,
+    /// Cancelled on Ctrl-C, downloads stop between chunks
+    cancel: CancellationToken,
+}
+
+impl ClientOptions {
+    fn client(&self, console: &Console) -> Client {
+        let mut client = Client::builder(&console.address.to_string(), console.port)
+            .connect_timeout(Some(self.connect_timeout))
+            .read_timeout(Some(self.read_timeout))
+            .timeout(self.timeout)
+            .user_agent(&self.user_agent)
+            .lenient_metadata(self.lenient)
+            .console_name(&console.name)
+            .cancellation_token(self.cancel.clone())
+            .build();
+        if let Some(rate) = self.per_client {
+            client = client.with_rate_limiter(Arc::new(RateLimiter::new(rate)));
+        }
+        if let Some(global) = &self.global {
+            client = client.with_rate_limiter(global.clone());
+        }
+
+        client
+    }
+}
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage the persistent download queue
+    Queue {
+        /// Queue state file
+        #[arg(long, default_value = "transfer-queue.json")]
+        file: PathBuf,
+        #[command(subcommand)]
+        command: QueueCommand,
+    },
+    /// List the items offered by every discovered console
+    List {
+        /// Output format: json, csv or table
+        #[arg(long, default_value = "table")]
+        format: OutputFormat,
+        /// How long to wait for consoles to answer discovery
+        #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+        wait: Duration,
+    },
+    /// Poll consoles and print metadata changes as they happen
+    Watch {
+        /// Time between polls, e.g. `30s`
+        #[arg(long, default_value = "10s", value_parser = humantime::parse_duration)]
+        interval: Duration,
+        /// How long to wait for consoles to answer discovery
+        #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+        wait: Duration,
+        /// Time between discoveries picking up new or moved consoles, e.g. `1m`
+        #[arg(long, default_value = "1m", value_parser = humantime::parse_duration)]
+        rediscover: Duration,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum QueueCommand {
+    /// Queue an item for download
+    Add {
+        console_id: String,
+        /// Item path or package family name
+        item: String,
+        destination: PathBuf,
+    },
+    /// Show all jobs
+    List,
+    /// Process pending jobs
+    Run {
+        /// Number of concurrent downloads
+        #[arg(long, default_value_t = 2)]
+        concurrency: usize,
+        /// How long to wait for the consoles of pending jobs to answer discovery
+        #[arg(long, default_value = "10s", value_parser = humantime::parse_duration)]
+        wait: Duration,
+    },
+}
+
+fn download_with_progress(client: &Client, item: &MetadataItem, offset: usize, content_length: usize, writer: &mut impl std::io::Write, sizing: ChunkSizing) -> Result<TransferSummary> {
     let progress_style = ProgressStyle::with_template("[{elapsed_precise}] [ETA: {eta}] {bar:40.cyan/blue} {bytes:>7}/{total_bytes:7} ({bytes_per_sec}) {msg}")?;
     let progress = ProgressBar::new(content_length as u64)
-        .with_style(progress_style);
-
-    let mut buf = vec![0u8; STEP_SIZE];
-    let written: usize = Client::iterate_range(content_length, STEP_SIZE).map(move |range|{
-        let resp = client.download_chunk(&item.path, &range).unwrap();
-        resp.into_reader().read_exact(&mut buf[..range.count()]).unwrap();
-        let wrote = writer.write(&buf[..range.count()]).unwrap();
-        progress.inc(wrote as u64);
+        .with_style(progress_style)
+        .with_position(offset as u64);
 
-        wrote
-    })
-    .sum();
+    let summary = client.download_chunks_adaptive(item, offset, content_length, writer, sizing, |update| {
+        progress.set_position(update.written as u64);
+        progress.set_message(format!("chunk {}", HumanBytes(update.next_chunk_size as u64)));
+    })?;
+    progress.finish();
 
-    Ok(written)
+    Ok(summary)
 }
 
-fn main() -> Result<()> {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+/// Write `summary` as pretty JSON to `path`
+fn write_summary(path: &Path, summary: &impl serde::Serialize) -> Result<()> {
+    std::fs::write(path, serde_json::to_vec_pretty(summary)?)
+        .with_context(|| format!("Failed writing summary to {path:?}"))
+}
 
+fn download_first(options: &ClientOptions, summary_json: Option<&Path>) -> Result<()> {
     let protocol = NetworkTransferProtocol {};
     let results = protocol.discover()
         .context("No network-transfer activate console found :(")?;
@@ -41,7 +166,7 @@ fn main() -> Result<()> {
     
     log::info!("Using console: {console:#?}");
 
-    let client = Client::from(console);
+    let client = options.client(console);
     let metadata = client.get_metadata()
         .context("Failed fetching metadata")?;
 
@@ -50,12 +175,209 @@ fn main() -> Result<()> {
 
     log::info!("Item: {item:#?}");
 
-    let mut file = std::fs::File::create(&item.package_family_name)?;
+    // Appending keeps what an interrupted run already downloaded
+    let content_length = client.get_item_filesize(item)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&item.package_family_name)?;
+    let offset = file.metadata()?.len() as usize;
+    anyhow::ensure!(offset <= content_length, "{} larger than item ({offset} > {content_length})", item.package_family_name);
+    if offset > 0 {
+        log::info!("Resuming {} at {offset}/{content_length}", item.package_family_name);
+    }
 
-    let size = download_with_progress(&client, item, &mut file)
+    let summary = download_with_progress(&client, item, offset, content_length, &mut file, options.sizing)
         .context("Failed downloading")?;
 
-    assert_eq!(size, file.stream_position()? as usize);
+    let written = file.metadata()?.len() as usize - offset;
+    anyhow::ensure!(summary.bytes == written, "Downloaded {} bytes but wrote {written}", summary.bytes);
+    println!("{summary}");
+    if let Some(path) = summary_json {
+        write_summary(path, &summary)?;
+    }
+
+    Ok(())
+}
+
+fn queue(file: PathBuf, command: QueueCommand, options: &ClientOptions, summary_json: Option<&Path>) -> Result<()> {
+    let mut queue = TransferQueue::open(&file)
+        .with_context(|| format!("Failed opening queue {file:?}"))?;
+
+    match command {
+        QueueCommand::Add { console_id, item, destination } => {
+            let id = queue.add(&console_id, &item, &destination)?;
+            println!("Queued job {id}: {item} -> {destination:?}");
+        }
+        QueueCommand::List => {
+            for job in queue.jobs() {
+                println!("{:>4} {:<10} {} {} -> {:?}", job.id, format!("{:?}", job.state), job.console_id, job.item_path, job.destination);
+                if let Some(error) = &job.error {
+                    println!("     {error}");
+                }
+            }
+        }
+        QueueCommand::Run { concurrency, wait } => {
+            let wanted: HashSet<String> = queue.jobs().iter()
+                .filter(|job| job.state == JobState::Pending)
+                .map(|job| job.console_id.clone())
+                .collect();
+            if wanted.is_empty() {
+                println!("No pending jobs");
+                return Ok(());
+            }
+
+            let consoles = NetworkTransferProtocol {}.discover_until(wait, |consoles| {
+                wanted.iter().all(|id| consoles.iter().any(|console| &console.id == id))
+            })?;
+            for id in wanted.iter().filter(|id| !consoles.iter().any(|console| &console.id == *id)) {
+                log::warn!("Console {id} did not answer discovery within {wait:?}");
+            }
+
+            let summaries = Mutex::new(vec![]);
+            let result = queue.run(concurrency, |job| {
+                let console = consoles.iter()
+                    .find(|console| console.id == job.console_id)
+                    .ok_or(network_transfer::error::Error::GeneralError(format!("Console {} not found", job.console_id)))?;
+
+                let summary = download_job(&options.client(console), job, options.sizing)?;
+                println!("Job {}: {summary}", job.id);
+                summaries.lock().unwrap_or_else(|e| e.into_inner()).push(summary);
+                Ok(())
+            });
+
+            // Summaries of the jobs done are kept even if the queue failed
+            let summaries = summaries.into_inner().unwrap_or_else(|e| e.into_inner());
+            let written = summary_json.map(|path| write_summary(path, &summaries)).transpose();
+            result?;
+            written?;
+
+            let failed = queue.jobs().iter().filter(|job| job.state == JobState::Failed).count();
+            println!("Queue finished, {failed} failed job(s)");
+        }
+    }
+
+    Ok(())
+}
+
+fn list(format: OutputFormat, wait: Duration, options: &ClientOptions) -> Result<()> {
+    let consoles = NetworkTransferProtocol {}.discover_all(wait)?;
+    if consoles.is_empty() {
+        anyhow::bail!("No network-transfer activate console found :(");
+    }
+
+    let mut rows = vec![];
+    for console in &consoles {
+        match options.client(console).get_metadata() {
+            Ok(metadata) => rows.extend(metadata.items.into_iter().map(|item| InventoryRow::new(console, item))),
+            Err(e) => log::warn!("Failed fetching metadata from {} ({}): {e:?}", console.name, console.id),
+        }
+    }
+
+    write_inventory(&mut std::io::stdout().lock(), format, &rows)?;
 
     Ok(())
 }
+
+fn print_diff(console: &Console, diff: &MetadataDiff) {
+    for item in &diff.added {
+        println!("[{}] + {} {}", console.name, item.package_family_name, item.version);
+    }
+    for item in &diff.removed {
+        println!("[{}] - {} {}", console.name, item.package_family_name, item.version);
+    }
+    for change in &diff.changed {
+        println!("[{}] ~ {} {} -> {}", console.name, change.new.package_family_name, change.old.version, change.new.version);
+    }
+}
+
+/// Sleep for `duration`, returning early with `false` once `cancel` fired
+fn sleep_unless_cancelled(cancel: &CancellationToken, duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    while !cancel.is_cancelled() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return true;
+        }
+        std::thread::sleep(left.min(Duration::from_millis(100)));
+    }
+
+    false
+}
+
+fn watch(interval: Duration, wait: Duration, rediscover: Duration, options: &ClientOptions) -> Result<()> {
+    // Keyed by console id, so a console coming back under a new address keeps its snapshot
+    let mut cache = MetadataCache::new();
+    let mut clients: Vec<(Console, Client)> = vec![];
+    let mut discovered_at: Option<Instant> = None;
+    loop {
+        if discovered_at.filter(|at| at.elapsed() < rediscover).is_none() {
+            let discovered = NetworkTransferProtocol {}.discover_all(wait);
+            match discovered {
+                Ok(consoles) => {
+                    for (gone, _) in clients.iter().filter(|(known, _)| !consoles.iter().any(|console| console.id == known.id)) {
+                        log::info!("Console {} ({}) no longer answers discovery", gone.name, gone.id);
+                    }
+                    if consoles.is_empty() {
+                        log::warn!("No network-transfer activate console found, retrying in {}", humantime::format_duration(rediscover));
+                    }
+                    clients = consoles.into_iter()
+                        .map(|console| {
+                            let client = options.client(&console);
+                            (console, client)
+                        })
+                        .collect();
+                }
+                Err(e) => log::warn!("Discovery failed, polling the known consoles: {e:?}"),
+            }
+            discovered_at = Some(Instant::now());
+        }
+
+        for (console, client) in &clients {
+            match cache.refresh(&console.id, client) {
+                Ok(diff) => print_diff(console, &diff),
+                Err(e) => log::warn!("Failed polling {} ({}): {e:?}", console.name, console.id),
+            }
+        }
+        if !sleep_unless_cancelled(&options.cancel, interval) {
+            return Ok(());
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    let args = Args::parse();
+
+    let cancel = CancellationToken::new();
+    ctrlc::set_handler({
+        let cancel = cancel.clone();
+        move || {
+            log::info!("Cancelling, partial downloads can be resumed");
+            cancel.cancel();
+        }
+    })?;
+    let options = ClientOptions {
+        per_client: args.rate_limit,
+        global: args.global_rate_limit.map(|rate| Arc::new(RateLimiter::new(rate))),
+        sizing: ChunkSizing {
+            initial: args.min_chunk_size,
+            min: args.min_chunk_size,
+            max: args.max_chunk_size,
+        },
+        connect_timeout: args.connect_timeout.unwrap_or(ClientBuilder::DEFAULT_CONNECT_TIMEOUT),
+        read_timeout: args.read_timeout.unwrap_or(ClientBuilder::DEFAULT_READ_TIMEOUT),
+        timeout: args.timeout,
+        user_agent: args.user_agent,
+        lenient: args.lenient,
+        cancel,
+    };
+
+    match args.command {
+        Some(Command::Queue { file, command }) => queue(file, command, &options, args.summary_json.as_deref()),
+        Some(Command::List { format, wait }) => list(format, wait, &options),
+        Some(Command::Watch { interval, wait, rediscover }) => watch(interval, wait, rediscover, &options),
+        None => download_first(&options, args.summary_json.as_deref()),
+    }
+}
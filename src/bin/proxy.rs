@@ -1,26 +1,75 @@
-use std::net::TcpStream;
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
 
 use anyhow::{Result};
+use clap::Parser;
 use env_logger::Env;
-use tokio::net::TcpListener;
-
-use std::io::{Read,Write};
+use network_transfer::{
+    capture::{CaptureFormat, CaptureWriter, CapturedRequest, Exchange, MessageReader, DEFAULT_MAX_BODY},
+    server::shutdown_signal,
+    SERVER_PORT,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinSet,
+};
 
 use hexdump::hexdump;
 
+#[derive(Parser, Debug)]
+#[command(about = "Man-in-the-middle proxy between a console and its peer")]
+struct Args {
+    /// Forward to this address instead of the connecting peer
+    #[arg(long)]
+    upstream: Option<IpAddr>,
+    /// Parse HTTP and record exchanges into this session file
+    #[arg(long)]
+    capture: Option<PathBuf>,
+    /// Session file format: har or jsonl
+    #[arg(long, default_value = "jsonl")]
+    format: CaptureFormat,
+    /// Maximum number of body bytes recorded per message
+    #[arg(long, default_value_t = DEFAULT_MAX_BODY)]
+    max_body: usize,
+    /// Time open connections get to complete on SIGINT/SIGTERM, e.g. `30s`
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    drain_timeout: Duration,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
-    let listener = TcpListener::bind(("0.0.0.0", 10248)).await?;
+    let args = Args::parse();
+    let listener = TcpListener::bind(("0.0.0.0", SERVER_PORT)).await?;
+
+    match args.capture {
+        Some(ref path) => {
+            let writer = CaptureWriter::create(path, args.format)?;
+            log::info!("Capturing HTTP exchanges to {path:?} ({:?})", args.format);
+            run_capture(listener, &args, Arc::new(Mutex::new(writer))).await
+        }
+        None => tokio::select! {
+            result = run_raw(listener, args.upstream) => result,
+            _ = shutdown_signal() => Ok(()),
+        },
+    }
+}
 
+/// Relay a single connection, hexdumping everything the console sends
+async fn run_raw(listener: TcpListener, upstream: Option<IpAddr>) -> Result<()> {
     let (instream, addr)  = listener.accept().await?;
-    dbg!(addr);
+    log::info!("Proxying raw connection from {addr}");
 
     let mut inbuf = [0u8; 4096 * 10];
     let mut outbuf = vec![0u8; 4096 * 10];
 
-    let mut outstream = TcpStream::connect((addr.ip(), 10248))?;
+    let mut outstream = TcpStream::connect((upstream.unwrap_or(addr.ip()), SERVER_PORT)).await?;
 
     loop {
         log::debug!("Trying to read from server socket");
@@ -42,13 +91,85 @@ async fn main() -> Result<()> {
 
         hexdump(&inbuf[..insize]);
 
-        let _ = outstream.write(&inbuf[..insize])?;
+        outstream.write_all(&inbuf[..insize]).await?;
         log::debug!("Wrote request to console");
 
         // let outsize = outstream.read(&mut outbuf)?;
-        let outsize = outstream.read_to_end(&mut outbuf)?;
+        let outsize = outstream.read_to_end(&mut outbuf).await?;
         log::debug!("Read response from console, bytes: {}", outsize);
         instream.try_write(&outbuf[..outsize])?;
         log::debug!("Wrote response to server socket -> console");
     }
-}
\ No newline at end of file
+}
+
+/// Relay every incoming connection, parsing and recording HTTP exchanges
+///
+/// On SIGINT/SIGTERM no more connections are accepted, open ones get the
+/// drain timeout to complete.
+async fn run_capture(listener: TcpListener, args: &Args, writer: Arc<Mutex<CaptureWriter>>) -> Result<()> {
+    let mut connections = JoinSet::new();
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    loop {
+        let (instream, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            // Reap finished relays so the set does not grow unbounded
+            Some(_) = connections.join_next() => continue,
+            _ = &mut shutdown => break,
+        };
+        let upstream = SocketAddr::new(args.upstream.unwrap_or(addr.ip()), SERVER_PORT);
+        let writer = writer.clone();
+        let max_body = args.max_body;
+
+        log::info!("Connection from {addr}, forwarding to {upstream}");
+        connections.spawn(async move {
+            if let Err(e) = relay_http(instream, addr, upstream, max_body, writer).await {
+                log::error!("Connection {addr} failed: {e:?}");
+            }
+        });
+    }
+
+    drop(listener);
+    let drained = tokio::time::timeout(args.drain_timeout, async {
+        while connections.join_next().await.is_some() {}
+    }).await;
+    if drained.is_err() {
+        log::warn!("{} connection(s) still open after {:?}, closing them", connections.len(), args.drain_timeout);
+        connections.shutdown().await;
+    }
+
+    Ok(())
+}
+
+async fn relay_http(instream: tokio::net::TcpStream, addr: SocketAddr, upstream: SocketAddr, max_body: usize, writer: Arc<Mutex<CaptureWriter>>) -> Result<()> {
+    let outstream = tokio::net::TcpStream::connect(upstream).await?;
+    let (in_read, mut in_write) = instream.into_split();
+    let (out_read, mut out_write) = outstream.into_split();
+    let mut requests = MessageReader::new(in_read);
+    let mut responses = MessageReader::new(out_read);
+
+    while let Some(raw_request) = requests.next_request().await? {
+        let started = SystemTime::now();
+        let timer = Instant::now();
+        let request = CapturedRequest::parse(&raw_request, max_body)?;
+        log::info!("{addr} > {} {} (range: {:?})", request.method, request.path, request.header("range"));
+
+        out_write.write_all(&raw_request).await?;
+
+        // Content responses can be gigabytes, they are streamed rather than buffered
+        let Some(response) = responses.relay_response(request.method == "HEAD", &mut in_write, max_body).await? else {
+            log::warn!("Upstream closed connection before responding");
+            break;
+        };
+        log::info!("{addr} < {} {} (content-range: {:?})", response.status, response.reason, response.header("content-range"));
+
+        let exchange = Exchange::new(Some(addr.to_string()), started, timer.elapsed(), request, response);
+        writer
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Capture writer poisoned"))?
+            .append(exchange)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use env_logger::Env;
+use network_transfer::{capture, generate_random_console_id, replay::{self, ReplayIndex}, server::shutdown_signal, Console, NetworkTransferProtocol, SERVER_PORT};
+use tokio_util::sync::CancellationToken;
+
+#[derive(Parser, Debug)]
+#[command(about = "Mock console replaying a recorded proxy capture")]
+struct Args {
+    /// Session file recorded by the proxy (HAR or JSONL)
+    capture: PathBuf,
+    #[arg(long, default_value_t = IpAddr::V4(Ipv4Addr::UNSPECIFIED))]
+    bind: IpAddr,
+    #[arg(long, default_value_t = SERVER_PORT)]
+    port: u16,
+    /// Announce the mock console via mDNS under this name
+    #[arg(long)]
+    announce: Option<String>,
+    /// Time in-flight responses get to complete on SIGINT/SIGTERM, e.g. `30s`
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    drain_timeout: Duration,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    let args = Args::parse();
+    let exchanges = capture::load(&args.capture)
+        .with_context(|| format!("Failed loading capture {:?}", args.capture))?;
+    let index = ReplayIndex::new(exchanges);
+    log::info!("Loaded {} recorded responses from {:?}", index.len(), args.capture);
+
+    let mut announcement = match args.announce {
+        Some(name) => {
+            let IpAddr::V4(address) = args.bind else {
+                anyhow::bail!("mDNS announcement requires an IPv4 bind address");
+            };
+            let console = Console {
+                address,
+                port: args.port,
+                id: generate_random_console_id(),
+                name,
+            };
+            Some(NetworkTransferProtocol {}.register(&console)?)
+        }
+        None => None,
+    };
+
+    let app = replay::router(index);
+
+    log::info!("Replaying @ {}:{}", args.bind, args.port);
+    let shutdown = CancellationToken::new();
+    let server = axum::Server::bind(&SocketAddr::new(args.bind, args.port))
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown.cancelled());
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => result?,
+        _ = shutdown_signal() => {
+            // Consoles should forget the mock while responses drain
+            if let Some(announcement) = announcement.take() {
+                match tokio::task::spawn_blocking(move || announcement.withdraw()).await {
+                    Ok(Err(e)) => log::warn!("Failed withdrawing announcement: {e:?}"),
+                    Err(e) => log::warn!("Failed withdrawing announcement: {e:?}"),
+                    Ok(Ok(())) => {}
+                }
+            }
+
+            shutdown.cancel();
+            match tokio::time::timeout(args.drain_timeout, &mut server).await {
+                Ok(result) => result?,
+                Err(_) => log::warn!("Responses still in flight after {:?}, abandoning them", args.drain_timeout),
+            }
+        }
+    }
+
+    if let Some(announcement) = announcement {
+        announcement.withdraw()?;
+    }
+
+    Ok(())
+}
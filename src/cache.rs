@@ -0,0 +1,158 @@
+//! Metadata caching and diffing.
+//!
+//! `/col/metadata` can be large, consoles are polled repeatedly and usually
+//! little changes between polls. [`MetadataCache`] keeps the last snapshot
+//! per console id and reports what changed as a [`MetadataDiff`].
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::Error,
+    models::{Metadata, MetadataItem},
+    Client,
+};
+
+/// Item whose version or size differs between two snapshots
+#[derive(Debug, Clone)]
+pub struct VersionChange {
+    pub old: MetadataItem,
+    pub new: MetadataItem,
+}
+
+/// Changes between two [`Metadata`] snapshots, items are matched by path
+#[derive(Debug, Clone, Default)]
+pub struct MetadataDiff {
+    pub added: Vec<MetadataItem>,
+    pub removed: Vec<MetadataItem>,
+    pub changed: Vec<VersionChange>,
+}
+
+impl MetadataDiff {
+    pub fn between(old: &Metadata, new: &Metadata) -> Self {
+        let old_items: HashMap<&str, &MetadataItem> = old.items.iter().map(|item| (item.path.as_str(), item)).collect();
+        let new_items: HashMap<&str, &MetadataItem> = new.items.iter().map(|item| (item.path.as_str(), item)).collect();
+
+        let mut diff = Self::default();
+        for item in &new.items {
+            match old_items.get(item.path.as_str()) {
+                None => diff.added.push(item.clone()),
+                Some(old) if old.version != item.version || old.size != item.size => diff.changed.push(VersionChange {
+                    old: (*old).clone(),
+                    new: item.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+        diff.removed = old.items
+            .iter()
+            .filter(|item| !new_items.contains_key(item.path.as_str()))
+            .cloned()
+            .collect();
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    metadata: Metadata,
+    fetched_at: Instant,
+}
+
+/// Last known metadata per console id
+#[derive(Debug, Default)]
+pub struct MetadataCache {
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl MetadataCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, console_id: &str) -> Option<&Metadata> {
+        self.entries.get(console_id).map(|entry| &entry.metadata)
+    }
+
+    /// Time since the metadata of `console_id` was stored
+    pub fn age(&self, console_id: &str) -> Option<Duration> {
+        self.entries.get(console_id).map(|entry| entry.fetched_at.elapsed())
+    }
+
+    /// Store a new snapshot, returning the changes against the previous one
+    ///
+    /// Every item counts as added for a console not seen before.
+    pub fn update(&mut self, console_id: &str, metadata: Metadata) -> MetadataDiff {
+        let diff = match self.entries.get(console_id) {
+            Some(entry) => MetadataDiff::between(&entry.metadata, &metadata),
+            None => MetadataDiff::between(&Metadata::default(), &metadata),
+        };
+        self.entries.insert(console_id.to_owned(), CacheEntry {
+            metadata,
+            fetched_at: Instant::now(),
+        });
+
+        diff
+    }
+
+    /// Fetch metadata of `console_id` with `client` and store it
+    pub fn refresh(&mut self, console_id: &str, client: &Client) -> Result<MetadataDiff, Error> {
+        let metadata = client.get_metadata()?;
+        Ok(self.update(console_id, metadata))
+    }
+
+    /// Cached metadata if younger than `max_age`, otherwise fetched anew
+    pub fn fetch(&mut self, console_id: &str, client: &Client, max_age: Duration) -> Result<&Metadata, Error> {
+        let stale = match self.age(console_id) {
+            Some(age) => age > max_age,
+            None => true,
+        };
+        if stale {
+            self.refresh(console_id, client)?;
+        }
+
+        self.get(console_id)
+            .ok_or(Error::GeneralError(format!("No metadata cached for {console_id}")))
+    }
+
+    pub fn remove(&mut self, console_id: &str) -> Option<Metadata> {
+        self.entries.remove(console_id).map(|entry| entry.metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(path: &str, version: &str) -> MetadataItem {
+        MetadataItem {
+            path: path.into(),
+            package_family_name: path.trim_start_matches('/').into(),
+            version: version.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_metadata_diff() {
+        let mut cache = MetadataCache::new();
+        let diff = cache.update("X1", Metadata { items: vec![item("/a", "1"), item("/b", "1")], ..Default::default() });
+        assert_eq!(diff.added.len(), 2);
+
+        let diff = cache.update("X1", Metadata { items: vec![item("/b", "2"), item("/c", "1")], ..Default::default() });
+        assert_eq!(diff.added.iter().map(|i| i.path.as_str()).collect::<Vec<_>>(), ["/c"]);
+        assert_eq!(diff.removed.iter().map(|i| i.path.as_str()).collect::<Vec<_>>(), ["/a"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!((diff.changed[0].old.version.as_str(), diff.changed[0].new.version.as_str()), ("1", "2"));
+
+        let unchanged = cache.get("X1").unwrap().clone();
+        assert!(cache.update("X1", unchanged).is_empty());
+        assert!(cache.get("X2").is_none());
+    }
+}
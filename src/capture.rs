@@ -0,0 +1,872 @@
+//! HTTP-aware traffic capture, used by the `proxy` binary.
+//!
+//! Requests are read off the wire as complete raw HTTP/1.1 messages,
+//! responses are streamed through as they arrive. Both are forwarded
+//! unchanged and recorded as [`Exchange`]s, keeping at most a bounded prefix
+//! of each body. Sessions can be written as JSONL (one exchange per line) or
+//! HAR 1.2 and loaded back with [`load`].
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Seek, SeekFrom, Write},
+    path::Path,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::error::Error;
+
+/// Default upper bound of body bytes stored per message
+pub const DEFAULT_MAX_BODY: usize = 1024 * 1024;
+
+const MAX_HEADERS: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+/// Captured message body
+///
+/// JSON and text bodies are stored as-is in `text`, everything else is
+/// stored base64 encoded with `encoding` set to `base64`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Body {
+    pub size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+impl Body {
+    fn capture(data: &[u8], mime_type: Option<&str>, max_body: usize) -> Self {
+        Self::capture_prefix(data, data.len(), mime_type, max_body)
+    }
+
+    /// Body of `size` bytes of which `prefix` was read
+    fn capture_prefix(prefix: &[u8], size: usize, mime_type: Option<&str>, max_body: usize) -> Self {
+        let stored = &prefix[..std::cmp::min(prefix.len(), max_body)];
+        let truncated = size > stored.len();
+        let is_text = mime_type
+            .map(|mime| mime.contains("json") || mime.starts_with("text/"))
+            .unwrap_or(false);
+
+        let (text, encoding) = if stored.is_empty() {
+            (None, None)
+        } else if is_text {
+            (Some(String::from_utf8_lossy(stored).into_owned()), None)
+        } else {
+            (Some(BASE64.encode(stored)), Some("base64".to_owned()))
+        };
+
+        Self {
+            size,
+            mime_type: mime_type.map(str::to_owned),
+            text,
+            encoding,
+            truncated,
+        }
+    }
+
+    /// Decoded body bytes, as far as they were captured
+    pub fn bytes(&self) -> Result<Vec<u8>, Error> {
+        match (&self.text, self.encoding.as_deref()) {
+            (None, _) => Ok(vec![]),
+            (Some(text), Some("base64")) => BASE64
+                .decode(text)
+                .map_err(|e| Error::GeneralError(format!("Invalid base64 body: {e}"))),
+            (Some(text), _) => Ok(text.as_bytes().to_vec()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CapturedRequest {
+    pub method: String,
+    pub path: String,
+    pub http_version: String,
+    pub headers: Vec<Header>,
+    pub body: Body,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CapturedResponse {
+    pub status: u16,
+    pub reason: String,
+    pub http_version: String,
+    pub headers: Vec<Header>,
+    pub body: Body,
+}
+
+/// One request/response pair
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Exchange {
+    /// Unix timestamp in milliseconds
+    pub started_at: u64,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    pub request: CapturedRequest,
+    pub response: CapturedResponse,
+}
+
+fn find_header<'a>(headers: &'a [Header], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|hdr| hdr.name.eq_ignore_ascii_case(name))
+        .map(|hdr| hdr.value.as_str())
+}
+
+impl CapturedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Parse a complete raw request, as returned by [`MessageReader::next_request`]
+    pub fn parse(raw: &[u8], max_body: usize) -> Result<Self, Error> {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut req = httparse::Request::new(&mut headers);
+        let head_len = match req.parse(raw)? {
+            httparse::Status::Complete(len) => len,
+            httparse::Status::Partial => Err(Error::GeneralError("Incomplete request head".into()))?,
+        };
+
+        let headers = convert_headers(req.headers);
+        let body = Body::capture(&raw[head_len..], find_header(&headers, "content-type"), max_body);
+
+        Ok(Self {
+            method: req.method.unwrap_or_default().to_owned(),
+            path: req.path.unwrap_or_default().to_owned(),
+            http_version: format!("HTTP/1.{}", req.version.unwrap_or(1)),
+            headers,
+            body,
+        })
+    }
+}
+
+impl CapturedResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Parse a complete raw response, as returned by [`MessageReader::next_response`]
+    ///
+    /// Chunked bodies are stored decoded, without chunk framing.
+    pub fn parse(raw: &[u8], max_body: usize) -> Result<Self, Error> {
+        let (mut response, head_len) = Self::parse_head(raw)?;
+        let body = match find_header(&response.headers, "transfer-encoding") {
+            Some(encoding) if encoding.to_ascii_lowercase().contains("chunked") => decode_chunked(&raw[head_len..])?,
+            _ => raw[head_len..].to_vec(),
+        };
+        response.body = Body::capture(&body, response.header("content-type"), max_body);
+
+        Ok(response)
+    }
+
+    /// Parse the head of a response, returning it with an empty body and the head length
+    fn parse_head(raw: &[u8]) -> Result<(Self, usize), Error> {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut resp = httparse::Response::new(&mut headers);
+        let head_len = match resp.parse(raw)? {
+            httparse::Status::Complete(len) => len,
+            httparse::Status::Partial => Err(Error::GeneralError("Incomplete response head".into()))?,
+        };
+
+        let response = Self {
+            status: resp.code.unwrap_or_default(),
+            reason: resp.reason.unwrap_or_default().to_owned(),
+            http_version: format!("HTTP/1.{}", resp.version.unwrap_or(1)),
+            headers: convert_headers(resp.headers),
+            body: Body::default(),
+        };
+
+        Ok((response, head_len))
+    }
+}
+
+fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut body = vec![];
+    loop {
+        match httparse::parse_chunk_size(data) {
+            Ok(httparse::Status::Complete((_, 0))) => return Ok(body),
+            Ok(httparse::Status::Complete((size_len, size))) => {
+                let chunk = data
+                    .get(size_len..size_len + size as usize)
+                    .ok_or(Error::GeneralError("Truncated chunk".into()))?;
+                body.extend_from_slice(chunk);
+                data = data.get(size_len + size as usize + 2..).unwrap_or_default();
+            }
+            _ => Err(Error::GeneralError("Invalid chunked body".into()))?,
+        }
+    }
+}
+
+fn convert_headers(headers: &[httparse::Header<'_>]) -> Vec<Header> {
+    headers
+        .iter()
+        .map(|hdr| Header {
+            name: hdr.name.to_owned(),
+            value: String::from_utf8_lossy(hdr.value).into_owned(),
+        })
+        .collect()
+}
+
+impl Exchange {
+    pub fn new(client: Option<String>, started: SystemTime, duration: Duration, request: CapturedRequest, response: CapturedResponse) -> Self {
+        Self {
+            started_at: started
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            duration_ms: duration.as_millis() as u64,
+            client,
+            request,
+            response,
+        }
+    }
+}
+
+enum BodyLength {
+    Fixed(usize),
+    Chunked,
+    UntilClose,
+}
+
+/// Reads raw HTTP/1.x messages off a stream
+///
+/// Bytes following a message (pipelined requests) are kept for the next call.
+pub struct MessageReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> MessageReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, buf: vec![] }
+    }
+
+    /// Read the next request, `None` if the peer closed the connection
+    pub async fn next_request(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let Some(head_len) = self.read_head(true).await? else {
+            return Ok(None);
+        };
+
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut req = httparse::Request::new(&mut headers);
+        req.parse(&self.buf[..head_len])?;
+        let length = match body_length(req.headers)? {
+            BodyLength::UntilClose => BodyLength::Fixed(0),
+            length => length,
+        };
+
+        self.read_body(head_len, length).await.map(Some)
+    }
+
+    /// Read the next response, `None` if the peer closed the connection
+    ///
+    /// `head_request` must be set if the response answers a `HEAD` request.
+    pub async fn next_response(&mut self, head_request: bool) -> Result<Option<Vec<u8>>, Error> {
+        let Some(head_len) = self.read_head(false).await? else {
+            return Ok(None);
+        };
+
+        let length = self.response_length(head_len, head_request)?;
+        self.read_body(head_len, length).await.map(Some)
+    }
+
+    /// Stream the next response into `sink`, `None` if the peer closed the connection
+    ///
+    /// Unlike [`Self::next_response`] the body is forwarded as it arrives and
+    /// only its first `max_body` bytes are kept, so content of any size is
+    /// relayed in constant memory. Chunked bodies are captured decoded.
+    pub async fn relay_response<W: AsyncWrite + Unpin>(&mut self, head_request: bool, sink: &mut W, max_body: usize) -> Result<Option<CapturedResponse>, Error> {
+        let Some(head_len) = self.read_head(false).await? else {
+            return Ok(None);
+        };
+
+        let length = self.response_length(head_len, head_request)?;
+        let (mut response, _) = CapturedResponse::parse_head(&self.buf[..head_len])?;
+        self.forward(head_len, sink, None).await?;
+
+        let mut body = BodyPrefix { data: vec![], size: 0, max: max_body };
+        match length {
+            BodyLength::Fixed(len) => self.forward(len, sink, Some(&mut body)).await?,
+            BodyLength::Chunked => loop {
+                let (size_len, size) = match httparse::parse_chunk_size(&self.buf) {
+                    Ok(httparse::Status::Complete(chunk)) => chunk,
+                    Ok(httparse::Status::Partial) => {
+                        if self.fill().await? == 0 {
+                            Err(Error::GeneralError("Connection closed mid-body".into()))?
+                        }
+                        continue;
+                    }
+                    Err(_) => Err(Error::GeneralError("Invalid chunk size".into()))?,
+                };
+                self.forward(size_len, sink, None).await?;
+                if size == 0 {
+                    // Optional trailer, then an empty line
+                    let end = loop {
+                        if self.buf.starts_with(b"\r\n") {
+                            break 2;
+                        }
+                        if let Some(end) = find_subslice(&self.buf, b"\r\n\r\n") {
+                            break end + 4;
+                        }
+                        if self.fill().await? == 0 {
+                            Err(Error::GeneralError("Connection closed mid-body".into()))?
+                        }
+                    };
+                    self.forward(end, sink, None).await?;
+                    break;
+                }
+                self.forward(size as usize, sink, Some(&mut body)).await?;
+                self.forward(2, sink, None).await?;
+            },
+            BodyLength::UntilClose => loop {
+                let read = self.buf.len();
+                self.forward(read, sink, Some(&mut body)).await?;
+                if self.fill().await? == 0 {
+                    break;
+                }
+            },
+        }
+        sink.flush().await?;
+
+        response.body = Body::capture_prefix(&body.data, body.size, response.header("content-type"), max_body);
+        Ok(Some(response))
+    }
+
+    fn response_length(&self, head_len: usize, head_request: bool) -> Result<BodyLength, Error> {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut resp = httparse::Response::new(&mut headers);
+        resp.parse(&self.buf[..head_len])?;
+        let code = resp.code.unwrap_or_default();
+        if head_request || (100..200).contains(&code) || code == 204 || code == 304 {
+            return Ok(BodyLength::Fixed(0));
+        }
+
+        body_length(resp.headers)
+    }
+
+    /// Write exactly `len` bytes to `sink`, as far as buffered then read on demand
+    async fn forward<W: AsyncWrite + Unpin>(&mut self, mut len: usize, sink: &mut W, mut body: Option<&mut BodyPrefix>) -> Result<(), Error> {
+        while len > 0 {
+            if self.buf.is_empty() && self.fill().await? == 0 {
+                Err(Error::GeneralError("Connection closed mid-body".into()))?
+            }
+            let available = std::cmp::min(len, self.buf.len());
+            sink.write_all(&self.buf[..available]).await?;
+            if let Some(body) = body.as_deref_mut() {
+                body.push(&self.buf[..available]);
+            }
+            self.buf.drain(..available);
+            len -= available;
+        }
+
+        Ok(())
+    }
+
+    async fn fill(&mut self) -> Result<usize, Error> {
+        let mut chunk = [0u8; 0x4000];
+        let read = self.inner.read(&mut chunk).await?;
+        self.buf.extend_from_slice(&chunk[..read]);
+        Ok(read)
+    }
+
+    async fn read_head(&mut self, request: bool) -> Result<Option<usize>, Error> {
+        loop {
+            if !self.buf.is_empty() {
+                let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+                let status = if request {
+                    httparse::Request::new(&mut headers).parse(&self.buf)?
+                } else {
+                    httparse::Response::new(&mut headers).parse(&self.buf)?
+                };
+
+                if let httparse::Status::Complete(head_len) = status {
+                    return Ok(Some(head_len));
+                }
+            }
+
+            if self.fill().await? == 0 {
+                return match self.buf.is_empty() {
+                    true => Ok(None),
+                    false => Err(Error::GeneralError("Connection closed mid-message".into())),
+                };
+            }
+        }
+    }
+
+    async fn read_body(&mut self, head_len: usize, length: BodyLength) -> Result<Vec<u8>, Error> {
+        let total = match length {
+            BodyLength::Fixed(len) => {
+                while self.buf.len() < head_len + len {
+                    if self.fill().await? == 0 {
+                        Err(Error::GeneralError("Connection closed mid-body".into()))?
+                    }
+                }
+                head_len + len
+            }
+            BodyLength::Chunked => {
+                let mut pos = head_len;
+                loop {
+                    match httparse::parse_chunk_size(&self.buf[pos..]) {
+                        Ok(httparse::Status::Complete((size_len, 0))) => {
+                            // Last chunk, followed by an optional trailer and an empty line
+                            let trailer = pos + size_len;
+                            let end = loop {
+                                if self.buf[trailer..].starts_with(b"\r\n") {
+                                    break trailer + 2;
+                                }
+                                if let Some(end) = find_subslice(&self.buf[trailer..], b"\r\n\r\n") {
+                                    break trailer + end + 4;
+                                }
+                                if self.fill().await? == 0 {
+                                    Err(Error::GeneralError("Connection closed mid-body".into()))?
+                                }
+                            };
+                            break end;
+                        }
+                        Ok(httparse::Status::Complete((size_len, size))) => {
+                            let next = pos + size_len + size as usize + 2;
+                            while self.buf.len() < next {
+                                if self.fill().await? == 0 {
+                                    Err(Error::GeneralError("Connection closed mid-body".into()))?
+                                }
+                            }
+                            pos = next;
+                        }
+                        Ok(httparse::Status::Partial) => {
+                            if self.fill().await? == 0 {
+                                Err(Error::GeneralError("Connection closed mid-body".into()))?
+                            }
+                        }
+                        Err(_) => Err(Error::GeneralError("Invalid chunk size".into()))?,
+                    }
+                }
+            }
+            BodyLength::UntilClose => {
+                while self.fill().await? != 0 {}
+                self.buf.len()
+            }
+        };
+
+        Ok(self.buf.drain(..total).collect())
+    }
+}
+
+/// First bytes of a relayed body, with the total size seen
+struct BodyPrefix {
+    data: Vec<u8>,
+    size: usize,
+    max: usize,
+}
+
+impl BodyPrefix {
+    fn push(&mut self, bytes: &[u8]) {
+        let keep = std::cmp::min(bytes.len(), self.max.saturating_sub(self.data.len()));
+        self.data.extend_from_slice(&bytes[..keep]);
+        self.size += bytes.len();
+    }
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn body_length(headers: &[httparse::Header<'_>]) -> Result<BodyLength, Error> {
+    let value = |name: &str| {
+        headers
+            .iter()
+            .find(|hdr| hdr.name.eq_ignore_ascii_case(name))
+            .map(|hdr| String::from_utf8_lossy(hdr.value).into_owned())
+    };
+
+    if let Some(encoding) = value("transfer-encoding") {
+        if encoding.to_ascii_lowercase().contains("chunked") {
+            return Ok(BodyLength::Chunked);
+        }
+    }
+
+    match value("content-length") {
+        Some(length) => length
+            .trim()
+            .parse()
+            .map(BodyLength::Fixed)
+            .map_err(|_| Error::GeneralError(format!("Invalid content-length: {length}"))),
+        None => Ok(BodyLength::UntilClose),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureFormat {
+    Har,
+    Jsonl,
+}
+
+impl FromStr for CaptureFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "har" => Ok(Self::Har),
+            "jsonl" => Ok(Self::Jsonl),
+            _ => Err(Error::GeneralError(format!("Unknown capture format: {s}"))),
+        }
+    }
+}
+
+/// Closes the entry list and the document of a HAR session
+const HAR_CLOSING: &str = "]}}";
+
+/// Writes exchanges to a session file
+///
+/// JSONL sessions are appended to line by line. HAR sessions get each entry
+/// written over the closing brackets, which are then written again, so the
+/// file on disk is always a complete document without keeping entries around.
+pub struct CaptureWriter {
+    format: CaptureFormat,
+    file: File,
+    entries: usize,
+}
+
+impl CaptureWriter {
+    pub fn create(path: impl AsRef<Path>, format: CaptureFormat) -> Result<Self, Error> {
+        let mut file = File::create(path)?;
+        if format == CaptureFormat::Har {
+            let empty = serde_json::to_string(&har::Har::from(&[][..]))?;
+            file.write_all(empty.as_bytes())?;
+            file.flush()?;
+        }
+
+        Ok(Self {
+            format,
+            file,
+            entries: 0,
+        })
+    }
+
+    pub fn append(&mut self, exchange: Exchange) -> Result<(), Error> {
+        match self.format {
+            CaptureFormat::Jsonl => {
+                serde_json::to_writer(&mut self.file, &exchange)?;
+                self.file.write_all(b"\n")?;
+            }
+            CaptureFormat::Har => {
+                self.file.seek(SeekFrom::End(-(HAR_CLOSING.len() as i64)))?;
+                if self.entries > 0 {
+                    self.file.write_all(b",")?;
+                }
+                self.file.write_all(b"\n")?;
+                serde_json::to_writer(&mut self.file, &har::Entry::from(&exchange))?;
+                self.file.write_all(HAR_CLOSING.as_bytes())?;
+            }
+        }
+        self.entries += 1;
+        self.file.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Load a session file written by [`CaptureWriter`], format is auto-detected
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<Exchange>, Error> {
+    let data = std::fs::read_to_string(path)?;
+    let is_har = serde_json::from_str::<serde_json::Value>(&data)
+        .map(|value| value.get("log").is_some())
+        .unwrap_or(false);
+
+    if is_har {
+        let har: har::Har = serde_json::from_str(&data)?;
+        return Ok(har.into());
+    }
+
+    BufReader::new(data.as_bytes())
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str::<Exchange>(&line?)?))
+        .collect()
+}
+
+/// Minimal HAR 1.2 document model
+mod har {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use serde::{Deserialize, Serialize};
+
+    use super::{Body, CapturedRequest, CapturedResponse, Exchange, Header};
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Har {
+        pub log: Log,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Log {
+        pub version: String,
+        pub creator: Creator,
+        pub entries: Vec<Entry>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Creator {
+        pub name: String,
+        pub version: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Entry {
+        pub started_date_time: String,
+        pub time: u64,
+        #[serde(default, rename = "_client", skip_serializing_if = "Option::is_none")]
+        pub client: Option<String>,
+        pub request: Request,
+        pub response: Response,
+        pub cache: serde_json::Value,
+        pub timings: Timings,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Request {
+        pub method: String,
+        pub url: String,
+        pub http_version: String,
+        pub headers: Vec<Header>,
+        pub query_string: Vec<Header>,
+        pub cookies: Vec<serde_json::Value>,
+        pub headers_size: i64,
+        pub body_size: i64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub post_data: Option<Body>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Response {
+        pub status: u16,
+        pub status_text: String,
+        pub http_version: String,
+        pub headers: Vec<Header>,
+        pub cookies: Vec<serde_json::Value>,
+        pub content: Body,
+        #[serde(rename = "redirectURL")]
+        pub redirect_url: String,
+        pub headers_size: i64,
+        pub body_size: i64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Timings {
+        pub send: u64,
+        pub wait: u64,
+        pub receive: u64,
+    }
+
+    impl From<&Exchange> for Entry {
+        fn from(exchange: &Exchange) -> Self {
+            let started = UNIX_EPOCH + Duration::from_millis(exchange.started_at);
+            let host = exchange.request.header("host").unwrap_or("localhost");
+            let request = &exchange.request;
+            let response = &exchange.response;
+
+            Self {
+                started_date_time: humantime::format_rfc3339_millis(started).to_string(),
+                time: exchange.duration_ms,
+                client: exchange.client.clone(),
+                request: Request {
+                    method: request.method.clone(),
+                    url: format!("http://{host}{}", request.path),
+                    http_version: request.http_version.clone(),
+                    headers: request.headers.clone(),
+                    query_string: vec![],
+                    cookies: vec![],
+                    headers_size: -1,
+                    body_size: request.body.size as i64,
+                    post_data: (request.body.size > 0).then(|| request.body.clone()),
+                },
+                response: Response {
+                    status: response.status,
+                    status_text: response.reason.clone(),
+                    http_version: response.http_version.clone(),
+                    headers: response.headers.clone(),
+                    cookies: vec![],
+                    content: response.body.clone(),
+                    redirect_url: String::new(),
+                    headers_size: -1,
+                    body_size: response.body.size as i64,
+                },
+                cache: serde_json::json!({}),
+                timings: Timings {
+                    send: 0,
+                    wait: exchange.duration_ms,
+                    receive: 0,
+                },
+            }
+        }
+    }
+
+    impl From<Entry> for Exchange {
+        fn from(entry: Entry) -> Self {
+            let started_at = humantime::parse_rfc3339_weak(&entry.started_date_time)
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|since| since.as_millis() as u64)
+                .unwrap_or_default();
+
+            let path = url::Url::parse(&entry.request.url)
+                .map(|url| match url.query() {
+                    Some(query) => format!("{}?{query}", url.path()),
+                    None => url.path().to_owned(),
+                })
+                .unwrap_or(entry.request.url);
+
+            Self {
+                started_at,
+                duration_ms: entry.time,
+                client: entry.client,
+                request: CapturedRequest {
+                    method: entry.request.method,
+                    path,
+                    http_version: entry.request.http_version,
+                    headers: entry.request.headers,
+                    body: entry.request.post_data.unwrap_or_default(),
+                },
+                response: CapturedResponse {
+                    status: entry.response.status,
+                    reason: entry.response.status_text,
+                    http_version: entry.response.http_version,
+                    headers: entry.response.headers,
+                    body: entry.response.content,
+                },
+            }
+        }
+    }
+
+    impl From<&[Exchange]> for Har {
+        fn from(exchanges: &[Exchange]) -> Self {
+            Self {
+                log: Log {
+                    version: "1.2".into(),
+                    creator: Creator {
+                        name: env!("CARGO_PKG_NAME").into(),
+                        version: env!("CARGO_PKG_VERSION").into(),
+                    },
+                    entries: exchanges.iter().map(Entry::from).collect(),
+                },
+            }
+        }
+    }
+
+    impl From<Har> for Vec<Exchange> {
+        fn from(har: Har) -> Self {
+            har.log.entries.into_iter().map(Exchange::from).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUEST: &[u8] = b"GET /col/metadata HTTP/1.1\r\nHost: 10.0.0.229:10248\r\nAccept: application/json\r\nx-contract-version: 1\r\n\r\n";
+    const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Type: text/json\r\nServer: Microsoft-HTTPAPI/2.0\r\nContent-Length: 12\r\n\r\n{\"items\":[]}";
+    const CONTENT: &[u8] = b"HTTP/1.1 206 OK\r\nContent-Type: application/octet-stream\r\nContent-Range: bytes 0-0/105205760\r\nContent-Length: 1\r\n\r\n\x7f";
+
+    fn exchange() -> Exchange {
+        Exchange::new(
+            Some("10.0.0.2:51234".into()),
+            UNIX_EPOCH + Duration::from_millis(1696725214000),
+            Duration::from_millis(12),
+            CapturedRequest::parse(REQUEST, DEFAULT_MAX_BODY).unwrap(),
+            CapturedResponse::parse(RESPONSE, DEFAULT_MAX_BODY).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_message_reader() {
+        let stream = [RESPONSE, CONTENT].concat();
+        let mut reader = MessageReader::new(&stream[..]);
+
+        assert_eq!(reader.next_response(false).await.unwrap().unwrap(), RESPONSE);
+        assert_eq!(reader.next_response(false).await.unwrap().unwrap(), CONTENT);
+        assert!(reader.next_response(false).await.unwrap().is_none());
+
+        let chunked: &[u8] = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n0\r\n\r\n";
+        let no_content: &[u8] = b"HTTP/1.1 204 No Content\r\n\r\n";
+        let stream = [chunked, no_content].concat();
+        let mut reader = MessageReader::new(&stream[..]);
+        let raw = reader.next_response(false).await.unwrap().unwrap();
+        assert_eq!(raw, chunked);
+        assert_eq!(CapturedResponse::parse(&raw, DEFAULT_MAX_BODY).unwrap().body.size, 4);
+        assert_eq!(reader.next_response(false).await.unwrap().unwrap(), no_content);
+    }
+
+    #[tokio::test]
+    async fn test_relay_response() {
+        let chunked: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n";
+        let stream = [CONTENT, chunked, RESPONSE].concat();
+        let mut reader = MessageReader::new(&stream[..]);
+        let mut relayed = vec![];
+
+        let content = reader.relay_response(false, &mut relayed, DEFAULT_MAX_BODY).await.unwrap().unwrap();
+        assert_eq!(content, CapturedResponse::parse(CONTENT, DEFAULT_MAX_BODY).unwrap());
+
+        // Only the first bytes are kept, everything is relayed
+        let truncated = reader.relay_response(false, &mut relayed, 6).await.unwrap().unwrap();
+        assert_eq!(truncated.body.size, 9);
+        assert!(truncated.body.truncated);
+        assert_eq!(truncated.body.text.as_deref(), Some("Wikipe"));
+
+        let head = reader.relay_response(true, &mut relayed, DEFAULT_MAX_BODY).await.unwrap().unwrap();
+        assert_eq!(head.body.size, 0);
+        assert_eq!(relayed, stream[..stream.len() - 12]);
+    }
+
+    #[test]
+    fn test_parse_messages() {
+        let request = CapturedRequest::parse(REQUEST, DEFAULT_MAX_BODY).unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/col/metadata");
+        assert_eq!(request.header("X-Contract-Version"), Some("1"));
+
+        let response = CapturedResponse::parse(RESPONSE, DEFAULT_MAX_BODY).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body.text.as_deref(), Some("{\"items\":[]}"));
+
+        let content = CapturedResponse::parse(CONTENT, DEFAULT_MAX_BODY).unwrap();
+        assert_eq!(content.reason, "OK");
+        assert_eq!(content.header("content-range"), Some("bytes 0-0/105205760"));
+        assert_eq!(content.body.encoding.as_deref(), Some("base64"));
+        assert_eq!(content.body.bytes().unwrap(), vec![0x7f]);
+    }
+
+    #[test]
+    fn test_session_roundtrip() {
+        let dir = std::env::temp_dir();
+        for (format, name) in [(CaptureFormat::Jsonl, "capture.jsonl"), (CaptureFormat::Har, "capture.har")] {
+            let path = dir.join(format!("{}-{name}", std::process::id()));
+            let mut writer = CaptureWriter::create(&path, format).unwrap();
+            writer.append(exchange()).unwrap();
+            writer.append(exchange()).unwrap();
+
+            let loaded = load(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded, vec![exchange(), exchange()]);
+        }
+    }
+}
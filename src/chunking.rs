@@ -0,0 +1,238 @@
+//! Adaptive chunk sizing for ranged downloads.
+//!
+//! [`AdaptiveRanges`] hands out consecutive [`Range`]s like
+//! [`Client::iterate_range`](crate::Client::iterate_range), but takes
+//! feedback after every chunk: the chunk size doubles while throughput keeps
+//! improving and halves when it drops sharply, on errors or timeouts, always
+//! within [`ChunkSizing`] bounds.
+use std::time::Duration;
+
+use crate::{error::Error, Range};
+
+/// Throughput has to beat the previous chunk by this factor to grow again
+const GROWTH_THRESHOLD: f64 = 1.05;
+
+/// Throughput below the previous chunk's times this factor shrinks the chunk size
+const SHRINK_THRESHOLD: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkSizing {
+    pub initial: usize,
+    pub min: usize,
+    pub max: usize,
+}
+
+impl ChunkSizing {
+    /// Fixed chunk size, never adapting
+    pub fn fixed(size: usize) -> Self {
+        Self {
+            initial: size,
+            min: size,
+            max: size,
+        }
+    }
+}
+
+impl Default for ChunkSizing {
+    fn default() -> Self {
+        Self {
+            initial: 0x10000,
+            min: 0x10000,
+            max: 0x1000000,
+        }
+    }
+}
+
+/// Progress report passed to download callbacks after every chunk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// Bytes written so far, including any resume offset
+    pub written: usize,
+    pub total: usize,
+    /// Size of the chunk just completed
+    pub chunk_size: usize,
+    /// Chunk size used for the next request
+    pub next_chunk_size: usize,
+    /// Throughput of the chunk just completed, bytes per second
+    pub throughput: f64,
+}
+
+#[derive(Debug)]
+pub struct AdaptiveRanges {
+    offset: usize,
+    end: usize,
+    chunk_size: usize,
+    sizing: ChunkSizing,
+    last_throughput: Option<f64>,
+}
+
+impl AdaptiveRanges {
+    /// Ranges covering `offset..end`
+    pub fn new(offset: usize, end: usize, sizing: ChunkSizing) -> Self {
+        let min = sizing.min.max(1);
+        let max = sizing.max.max(min);
+
+        Self {
+            offset,
+            end,
+            chunk_size: sizing.initial.clamp(min, max),
+            sizing: ChunkSizing {
+                initial: sizing.initial,
+                min,
+                max,
+            },
+            last_throughput: None,
+        }
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Feed back a successfully transferred chunk, returns its throughput
+    pub fn record_success(&mut self, bytes: usize, elapsed: Duration) -> f64 {
+        let throughput = bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        let (improved, dropped) = match self.last_throughput {
+            Some(last) => (throughput > last * GROWTH_THRESHOLD, throughput < last * SHRINK_THRESHOLD),
+            None => (true, false),
+        };
+
+        // Short final chunks say nothing about the chunk size
+        if dropped {
+            self.chunk_size = (self.chunk_size / 2).max(self.sizing.min);
+        } else if bytes == self.chunk_size && improved {
+            self.chunk_size = (self.chunk_size * 2).min(self.sizing.max);
+        }
+        self.last_throughput = Some(throughput);
+
+        throughput
+    }
+
+    /// Feed back a failed chunk, it is handed out again with a smaller size
+    pub fn record_failure(&mut self, range: &Range) {
+        self.offset = self.offset.min(range.first());
+        self.chunk_size = (self.chunk_size / 2).max(self.sizing.min);
+        self.last_throughput = None;
+    }
+}
+
+impl Iterator for AdaptiveRanges {
+    type Item = Range;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.end {
+            return None;
+        }
+
+        let size = self.chunk_size.min(self.end - self.offset);
+        let range = Range::from_len(self.offset, size).ok()?;
+        self.offset += size;
+
+        Some(range)
+    }
+}
+
+/// Parse a size like `65536`, `64K`, `16M` or `1G` (binary multiples, an optional
+/// trailing `B` or `iB` is accepted) into bytes
+pub fn parse_size(size: &str) -> Result<usize, Error> {
+    let trimmed = size.trim();
+    let trimmed = trimmed
+        .strip_suffix("iB")
+        .or_else(|| trimmed.strip_suffix(['b', 'B']))
+        .unwrap_or(trimmed);
+    let (digits, multiplier) = match trimmed.char_indices().last() {
+        Some((idx, 'k' | 'K')) => (&trimmed[..idx], 1 << 10),
+        Some((idx, 'm' | 'M')) => (&trimmed[..idx], 1 << 20),
+        Some((idx, 'g' | 'G')) => (&trimmed[..idx], 1 << 30),
+        _ => (trimmed, 1),
+    };
+
+    digits
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|value| value.checked_mul(multiplier))
+        .filter(|value| *value > 0)
+        .ok_or(Error::GeneralError(format!("Invalid size: {size}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZING: ChunkSizing = ChunkSizing {
+        initial: 100,
+        min: 50,
+        max: 400,
+    };
+
+    #[test]
+    fn test_grow_and_shrink() {
+        let mut ranges = AdaptiveRanges::new(0, 10_000, SIZING);
+
+        // Improving throughput grows up to max
+        for (expected, millis) in [(100, 100), (200, 100), (400, 100), (400, 100)] {
+            let range = ranges.next().unwrap();
+            assert_eq!(range.count(), expected);
+            ranges.record_success(range.count(), Duration::from_millis(millis));
+        }
+
+        // Errors shrink down to min and rewind
+        let failed = ranges.next().unwrap();
+        ranges.record_failure(&failed);
+        assert_eq!(ranges.chunk_size(), 200);
+        assert_eq!(ranges.next().unwrap().first(), failed.first());
+        for _ in 0..5 {
+            ranges.record_failure(&failed);
+        }
+        assert_eq!(ranges.chunk_size(), 50);
+    }
+
+    #[test]
+    fn test_steady_throughput_holds_size() {
+        let mut ranges = AdaptiveRanges::new(0, 10_000, SIZING);
+        let range = ranges.next().unwrap();
+        ranges.record_success(range.count(), Duration::from_millis(100));
+        // Twice the bytes in twice the time: no improvement
+        let range = ranges.next().unwrap();
+        ranges.record_success(range.count(), Duration::from_millis(200));
+        assert_eq!(ranges.chunk_size(), 200);
+    }
+
+    #[test]
+    fn test_throughput_drop_shrinks_size() {
+        let mut ranges = AdaptiveRanges::new(0, 10_000, SIZING);
+        for millis in [100, 100] {
+            let range = ranges.next().unwrap();
+            ranges.record_success(range.count(), Duration::from_millis(millis));
+        }
+        assert_eq!(ranges.chunk_size(), 400);
+
+        // 400 bytes in a second, a fifth of the previous throughput
+        let range = ranges.next().unwrap();
+        ranges.record_success(range.count(), Duration::from_millis(1000));
+        assert_eq!(ranges.chunk_size(), 200);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("65536").unwrap(), 65536);
+        assert_eq!(parse_size("64K").unwrap(), 64 << 10);
+        assert_eq!(parse_size("16MiB").unwrap(), 16 << 20);
+        assert_eq!(parse_size("1gb").unwrap(), 1 << 30);
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("big").is_err());
+    }
+
+    #[test]
+    fn test_covers_span() {
+        let mut ranges = AdaptiveRanges::new(1000, 5000, SIZING);
+        let mut expected = 1000;
+        while let Some(range) = ranges.next() {
+            assert_eq!(range.first(), expected);
+            expected = range.last() + 1;
+            ranges.record_success(range.count(), Duration::from_millis(10));
+        }
+        assert_eq!(expected, 5000);
+    }
+}
@@ -0,0 +1,144 @@
+//! Content responses mirroring the console.
+//!
+//! A console answers ranged requests with `206 OK` rather than
+//! `206 Partial Content`, sends no `accept-ranges` header and orders its
+//! headers as below. Serve with title case headers
+//! (`http1_title_case_headers`) to match the casing as well.
+//!
+//! ```text
+//! HTTP/1.1 206 OK
+//! Content-Type: application/octet-stream
+//! Content-Range: bytes 0-0/105205760
+//! Server: Microsoft-HTTPAPI/2.0
+//! Date: Sun, 08 Oct 2023 00:18:01 GMT
+//! Content-Length: 1
+//! ```
+use std::{future::Future, io::SeekFrom, time::SystemTime};
+
+use axum::{
+    body::{boxed, BoxBody, Empty, StreamBody},
+    http::{header, HeaderValue, StatusCode},
+    response::Response,
+};
+use hyper::ext::ReasonPhrase;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    error::Error,
+    provider::ContentProvider,
+    range::{ContentRange, RangeRequest},
+    ContentPath, Range,
+};
+
+/// `Server` header sent by consoles
+pub const SERVER: &str = "Microsoft-HTTPAPI/2.0";
+
+/// Respond with `range` of the `total` bytes readable from `reader`
+///
+/// Without a range the full content is sent with `200 OK`, an unsatisfiable
+/// range gets `416`. Returns the byte range the body covers, if any.
+pub async fn content_response<R>(mut reader: R, total: usize, range: Option<RangeRequest>) -> Result<(Response, Option<Range>), Error>
+where
+    R: AsyncRead + AsyncSeek + Unpin + Send + 'static,
+{
+    ranged_response(total, range, None, |served| async move {
+        reader.seek(SeekFrom::Start(served.first() as u64)).await?;
+        Ok(reader.take(served.count() as u64))
+    })
+    .await
+}
+
+/// Respond with `range` of the item at `path` offered by `provider`
+///
+/// Like [`content_response`], unknown paths fail with [`Error::NotFound`].
+/// The provider's entity tag is sent as `ETag`, a range requested with an
+/// `if_range` not matching it gets the full content, as it is of another
+/// version.
+pub async fn provider_response(provider: &dyn ContentProvider, path: &ContentPath, range: Option<RangeRequest>, if_range: Option<&str>) -> Result<(Response, Option<Range>), Error> {
+    let total = provider.size(path).await?;
+    let etag = provider.etag(path).await?;
+    let range = match if_range {
+        Some(if_range) if etag.as_deref() != Some(if_range) => {
+            log::info!("Sending all of {} for stale If-Range {if_range}", path.to_url_path());
+            None
+        }
+        _ => range,
+    };
+
+    ranged_response(total, range, etag.as_deref(), |served| async move {
+        Ok(provider.open(path, served).await?.reader)
+    })
+    .await
+}
+
+/// Resolve `range` against `total` and respond with the body `open` reads for it
+///
+/// `open` is only called for a non-empty range to serve.
+async fn ranged_response<F, Fut, B>(total: usize, range: Option<RangeRequest>, etag: Option<&str>, open: F) -> Result<(Response, Option<Range>), Error>
+where
+    F: FnOnce(Range) -> Fut,
+    Fut: Future<Output = Result<B, Error>>,
+    B: AsyncRead + Send + 'static,
+{
+    let served = match range.map(|range| range.resolve(total)) {
+        Some(None) => return Ok((unsatisfiable(total)?, None)),
+        Some(served) => served,
+        None => Range::from_len(0, total).ok(),
+    };
+
+    let body = match served {
+        Some(served) => boxed(StreamBody::new(ReaderStream::new(open(served).await?))),
+        None => boxed(Empty::new()),
+    };
+
+    Ok((respond(total, range, served, body, etag)?, served))
+}
+
+/// `416 Range Not Satisfiable` for content of `total` bytes
+fn unsatisfiable(total: usize) -> Result<Response, Error> {
+    let mut response = Response::new(boxed(Empty::new()));
+    *response.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_RANGE, header_value(ContentRange::unsatisfied(total).to_string())?);
+    headers.insert(header::SERVER, HeaderValue::from_static(SERVER));
+    headers.insert(header::DATE, http_date());
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(0));
+
+    Ok(response)
+}
+
+/// Response carrying `served` of `total` bytes in `body`, headers in console order
+fn respond(total: usize, range: Option<RangeRequest>, served: Option<Range>, body: BoxBody, etag: Option<&str>) -> Result<Response, Error> {
+    let length = served.map(|served| served.count()).unwrap_or_default();
+
+    let mut response = Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/octet-stream"));
+    if let (Some(_), Some(served)) = (range, served) {
+        headers.insert(header::CONTENT_RANGE, header_value(ContentRange::new(served, total)?.to_string())?);
+    }
+    headers.insert(header::SERVER, HeaderValue::from_static(SERVER));
+    headers.insert(header::DATE, http_date());
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+    // Consoles send none, only providers telling versions apart do
+    if let Some(etag) = etag {
+        headers.insert(header::ETAG, header_value(etag.to_owned())?);
+    }
+
+    if range.is_some() {
+        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+        response.extensions_mut().insert(ReasonPhrase::from_static(b"OK"));
+    }
+
+    Ok(response)
+}
+
+fn header_value(value: String) -> Result<HeaderValue, Error> {
+    HeaderValue::try_from(value).map_err(|e| Error::GeneralError(format!("Invalid header value: {e}")))
+}
+
+fn http_date() -> HeaderValue {
+    let date = httpdate::fmt_http_date(SystemTime::now());
+    HeaderValue::try_from(date).expect("HTTP date is a valid header value")
+}
@@ -11,6 +11,23 @@ pub enum Error {
     IoError(#[from] std::io::Error),
     #[error("Timeout Error")]
     TimeoutError(#[from] RecvTimeoutError),
+    #[error("JSON Error")]
+    JsonError(#[from] serde_json::Error),
+    #[error("HTTP Parse Error")]
+    HttpParseError(#[from] httparse::Error),
+    #[error("Watch Error")]
+    WatchError(#[from] notify::Error),
+    #[error("Unsupported contract version {requested}, supported: {supported:?}")]
+    UnsupportedContractVersion {
+        requested: crate::ContractVersion,
+        supported: Vec<crate::ContractVersion>,
+    },
+    #[error("Invalid range: {0}")]
+    InvalidRange(String),
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("Not found: {0}")]
+    NotFound(String),
     #[error("GeneralError")]
     GeneralError(String),
 }
\ No newline at end of file
@@ -24,12 +24,38 @@
 ///
 pub mod models;
 pub mod error;
-
-use std::{time::Duration, net::Ipv4Addr};
+pub mod capture;
+pub mod replay;
+pub mod push;
+pub mod queue;
+pub mod throttle;
+pub mod access;
+pub mod metrics;
+pub mod range;
+pub mod chunking;
+pub mod summary;
+pub mod cache;
+pub mod report;
+pub mod content;
+pub mod provider;
+pub mod vfs;
+pub mod watch;
+pub mod server;
+pub mod admin;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+
+use std::{time::Duration, net::Ipv4Addr, str::FromStr, sync::Arc};
 use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
 use rand::{thread_rng, Rng};
+use tokio_util::sync::CancellationToken;
 use url::Url;
-use crate::error::Error;
+use uuid::Uuid;
+use crate::{chunking::{AdaptiveRanges, ChunkSizing, Progress}, error::Error, summary::TransferSummary, throttle::RateLimiter};
+
+pub use range::Range;
+pub use server::{Server, ServerBuilder};
 
 pub const SERVER_PORT: u16 = 10248;
 
@@ -38,29 +64,107 @@ pub fn generate_random_console_id() -> String {
     format!("X{}", hex::encode(arr1))
 }
 
-#[derive(Debug, PartialEq, Eq, Default)]
-pub struct Range {
-    first_byte: usize,
-    last_byte: usize,
+/// Characters escaped in the file name part of a content path
+const CONTENT_NAME_ESCAPE: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'/').add(b'?')
+    .add(b'<').add(b'>').add(b'`').add(b'{').add(b'}');
+
+/// Content location as served below `/col/content/`
+///
+/// The decoded form is `{DRIVE-UUID}#name`, e.g.
+/// `{A89ECE52-7E8E-444F-BBD0-C68B76C2ECA4}#11032Reconco.XboxControllerTester_thvmwcgtjwwvy`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ContentPath {
+    pub drive_id: Uuid,
+    pub name: String,
 }
 
-impl Range {
-    pub fn new(first: usize, last: usize) -> Self {
+impl ContentPath {
+    pub fn new(drive_id: Uuid, name: &str) -> Self {
         Self {
-            first_byte: first,
-            last_byte: last
+            drive_id,
+            name: name.to_owned(),
         }
     }
 
-    pub fn count(&self) -> usize {
-        self.last_byte - self.first_byte + 1
+    /// Percent-encoded request path, as used in `MetadataItem::path`
+    pub fn to_url_path(&self) -> String {
+        format!(
+            "/col/content/%7B{}%7D%23{}",
+            self.drive_id.hyphenated().to_string().to_uppercase(),
+            utf8_percent_encode(&self.name, CONTENT_NAME_ESCAPE)
+        )
+    }
+
+    /// Parse a percent-encoded request path, as used in `MetadataItem::path`
+    pub fn from_url_path(path: &str) -> Result<Self, Error> {
+        let segment = path
+            .strip_prefix("/col/content/")
+            .ok_or(Error::GeneralError(format!("Not a content path: {path}")))?;
+
+        percent_decode_str(segment)
+            .decode_utf8()
+            .map_err(|_| Error::GeneralError(format!("Invalid content path encoding: {path}")))?
+            .parse()
+    }
+}
+
+impl FromStr for ContentPath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (drive_id, name) = s
+            .split_once('#')
+            .ok_or(Error::GeneralError(format!("Content path without drive id: {s}")))?;
+        let drive_id = Uuid::parse_str(drive_id)
+            .map_err(|_| Error::GeneralError(format!("Invalid drive id: {drive_id}")))?;
+
+        Ok(Self::new(drive_id, name))
+    }
+}
+
+/// Version of the `/col/*` HTTP contract, sent as `x-contract-version`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ContractVersion(pub u32);
+
+impl ContractVersion {
+    pub const HEADER: &'static str = "x-contract-version";
+    pub const V1: Self = Self(1);
+    /// Versions this crate knows how to speak, client and server side
+    pub const SUPPORTED: &'static [Self] = &[Self::V1];
+
+    pub fn is_supported(&self) -> bool {
+        Self::SUPPORTED.contains(self)
+    }
+}
+
+impl Default for ContractVersion {
+    fn default() -> Self {
+        Self::V1
+    }
+}
+
+impl std::fmt::Display for ContractVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for ContractVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim()
+            .parse()
+            .map(Self)
+            .map_err(|_| Error::GeneralError(format!("Invalid contract version: {s}")))
     }
 }
 
 #[derive(Debug)]
 pub struct NetworkTransferProtocol {}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct Console {
     pub address: Ipv4Addr,
     pub port: u16,
@@ -126,45 +230,298 @@ impl NetworkTransferProtocol {
         Ok(vec![Console::from(result)])
     }
 
+    /// Collect every console answering within `wait`
+    pub fn discover_all(&self, wait: Duration) -> Result<Vec<Console>, Error> {
+        self.discover_until(wait, |_| false)
+    }
+
+    /// Collect consoles answering within `wait`, stopping early once `found_all` returns true
+    pub fn discover_until(&self, wait: Duration, mut found_all: impl FnMut(&[Console]) -> bool) -> Result<Vec<Console>, Error> {
+        let mdns = ServiceDaemon::new()?;
+        let receiver = mdns.browse(Self::SERVICE_TYPE)?;
+        let deadline = std::time::Instant::now() + wait;
+
+        let mut consoles: Vec<Console> = vec![];
+        while let Some(remaining) = deadline.checked_duration_since(std::time::Instant::now()) {
+            match receiver.recv_timeout(remaining) {
+                Ok(ServiceEvent::ServiceResolved(info)) => {
+                    log::info!("Resolved a new service: {}", info.get_fullname());
+                    let console = Console::from(info);
+                    if !consoles.iter().any(|known| known.id == console.id) {
+                        consoles.push(console);
+                    }
+                    if found_all(&consoles) {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        mdns.shutdown()?;
+
+        Ok(consoles)
+    }
+
     pub fn announce(&self, console_info: &Console) -> Result<(), Error> {
+        let _announcement = self.register(console_info)?;
+
+        std::thread::sleep(Duration::from_secs(60 * 5));
+        Ok(())
+    }
+
+    /// Publish `console_info` until the returned [`Announcement`] is withdrawn or dropped
+    pub fn register(&self, console_info: &Console) -> Result<Announcement, Error> {
         // Create a daemon
         let mdns = ServiceDaemon::new()?;
 
         let service_info = Self::build_service_info(console_info)?;
+        let fullname = service_info.get_fullname().to_owned();
 
         // Register with the daemon, which publishes the service.
         mdns.register(service_info)?;
 
-        std::thread::sleep(Duration::from_secs(60 * 5));
+        Ok(Announcement {
+            mdns: Some(mdns),
+            fullname,
+        })
+    }
+}
+
+/// Registered mDNS service, see [`NetworkTransferProtocol::register`]
+pub struct Announcement {
+    mdns: Option<ServiceDaemon>,
+    fullname: String,
+}
+
+impl Announcement {
+    /// Unregister the service, sending a goodbye so clients forget it right away
+    pub fn withdraw(mut self) -> Result<(), Error> {
+        self.unregister()
+    }
+
+    fn unregister(&mut self) -> Result<(), Error> {
+        let Some(mdns) = self.mdns.take() else {
+            return Ok(());
+        };
+
+        log::info!("Withdrawing announcement of {}", self.fullname);
+        let status = mdns.unregister(&self.fullname)?;
+        if status.recv_timeout(Duration::from_secs(1)).is_err() {
+            log::warn!("No confirmation unregistering {}", self.fullname);
+        }
+        mdns.shutdown()?;
+
         Ok(())
     }
 }
 
+impl Drop for Announcement {
+    fn drop(&mut self) {
+        if let Err(e) = self.unregister() {
+            log::warn!("Failed withdrawing announcement of {}: {e:?}", self.fullname);
+        }
+    }
+}
+
+
+/// How [`ItemStat::size`] was determined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeSource {
+    Head,
+    RangeProbe,
+    Metadata,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemStat {
+    pub size: usize,
+    pub source: SizeSource,
+    pub accepts_ranges: bool,
+    pub content_type: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl ItemStat {
+    fn from_response(resp: &ureq::Response, size: usize, source: SizeSource) -> Self {
+        let header = |name| resp.header(name).map(str::to_owned);
+
+        Self {
+            size,
+            source,
+            accepts_ranges: resp.status() == 206
+                || resp.header("accept-ranges").is_some_and(|value| value.contains("bytes")),
+            content_type: header("content-type"),
+            etag: header("etag"),
+            last_modified: header("last-modified"),
+        }
+    }
+}
 
 pub struct Client {
     address: String,
     port: u16,
     client: ureq::Agent,
+    contract_version: ContractVersion,
+    rate_limiters: Vec<Arc<RateLimiter>>,
+    cancel: CancellationToken,
+    lenient_metadata: bool,
+    console_name: Option<String>,
 }
 
 impl From<&Console> for Client {
     fn from(value: &Console) -> Self {
-        Self::new(&value.address.to_string(), value.port)
+        ClientBuilder::new(&value.address.to_string(), value.port)
+            .console_name(&value.name)
+            .build()
     }
 }
 
-impl Client {
-    pub fn new(address: &str, port: u16) -> Self {
-        let agent = ureq::builder()
-            .user_agent("CopyOnLanSvc")
-            .build();
+/// Configures timeouts, user agent and cancellation of a [`Client`]
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    address: String,
+    port: u16,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    user_agent: String,
+    cancel: CancellationToken,
+    lenient_metadata: bool,
+    console_name: Option<String>,
+}
 
+impl ClientBuilder {
+    pub const DEFAULT_USER_AGENT: &'static str = "CopyOnLanSvc";
+    pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+    pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub fn new(address: &str, port: u16) -> Self {
         Self {
             address: address.to_string(),
             port,
-            client: agent,
+            connect_timeout: Some(Self::DEFAULT_CONNECT_TIMEOUT),
+            read_timeout: Some(Self::DEFAULT_READ_TIMEOUT),
+            timeout: None,
+            user_agent: Self::DEFAULT_USER_AGENT.to_string(),
+            cancel: CancellationToken::new(),
+            lenient_metadata: false,
+            console_name: None,
+        }
+    }
+
+    /// Timeout for establishing the TCP connection, `None` to wait indefinitely
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Timeout for each individual socket read, `None` to wait indefinitely
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Overall timeout of a single request, including reading the body
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Token aborting downloads between chunks once cancelled
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Skip and log metadata items failing to parse instead of failing the whole list
+    pub fn lenient_metadata(mut self, lenient: bool) -> Self {
+        self.lenient_metadata = lenient;
+        self
+    }
+
+    /// Name of the console, reported in [`TransferSummary`]
+    pub fn console_name(mut self, name: &str) -> Self {
+        self.console_name = Some(name.to_string());
+        self
+    }
+
+    pub fn build(self) -> Client {
+        let mut agent = ureq::builder()
+            .user_agent(&self.user_agent);
+        if let Some(timeout) = self.connect_timeout {
+            agent = agent.timeout_connect(timeout);
+        }
+        if let Some(timeout) = self.read_timeout {
+            agent = agent.timeout_read(timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            agent = agent.timeout(timeout);
+        }
+
+        Client {
+            address: self.address,
+            port: self.port,
+            client: agent.build(),
+            contract_version: ContractVersion::default(),
+            rate_limiters: vec![],
+            cancel: self.cancel,
+            lenient_metadata: self.lenient_metadata,
+            console_name: self.console_name,
         }
     }
+}
+
+impl Client {
+    pub fn new(address: &str, port: u16) -> Self {
+        ClientBuilder::new(address, port).build()
+    }
+
+    pub fn builder(address: &str, port: u16) -> ClientBuilder {
+        ClientBuilder::new(address, port)
+    }
+
+    /// Console name given to the builder, its address otherwise
+    pub fn console_name(&self) -> String {
+        match &self.console_name {
+            Some(name) => name.clone(),
+            None => format!("{}:{}", self.address, self.port),
+        }
+    }
+
+    /// Token cancelling this client's downloads
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    /// Fail with [`Error::Cancelled`] once the cancellation token fired
+    fn check_cancelled(&self) -> Result<(), Error> {
+        match self.cancel.is_cancelled() {
+            true => Err(Error::Cancelled),
+            false => Ok(()),
+        }
+    }
+
+    /// Throttle chunk downloads by `limiter`
+    ///
+    /// Can be called repeatedly, e.g. with a limiter of its own and one shared
+    /// by all clients for a global limit.
+    pub fn with_rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiters.push(limiter);
+        self
+    }
+
+    /// Contract version to request, defaults to [`ContractVersion::V1`]
+    pub fn with_contract_version(mut self, version: ContractVersion) -> Self {
+        self.contract_version = version;
+        self
+    }
 
     fn get_url(&self, path: &str) -> Url {
         let host = format!("http://{}:{}", self.address, self.port);
@@ -175,98 +532,335 @@ impl Client {
     }
  
     pub fn get_metadata(&self) -> Result<models::Metadata, Error> {
+        self.get_metadata_versioned().map(|(metadata, _)| metadata)
+    }
+
+    /// Fetch metadata along with the contract version reported by the server, if any
+    pub fn get_metadata_versioned(&self) -> Result<(models::Metadata, Option<ContractVersion>), Error> {
+        let (value, version) = self.fetch_metadata()?;
+        if !self.lenient_metadata {
+            return Ok((serde_json::from_value(value)?, version));
+        }
+
+        let (metadata, skipped) = models::Metadata::from_value_lenient(value)?;
+        for item in skipped {
+            log::warn!("Skipping metadata item {}: {} ({})", item.index, item.error, item.raw);
+        }
+
+        Ok((metadata, version))
+    }
+
+    /// Fetch metadata, returning items that failed to parse separately
+    pub fn get_metadata_lenient(&self) -> Result<(models::Metadata, Vec<models::SkippedItem>), Error> {
+        let (value, _) = self.fetch_metadata()?;
+        Ok(models::Metadata::from_value_lenient(value)?)
+    }
+
+    fn fetch_metadata(&self) -> Result<(serde_json::Value, Option<ContractVersion>), Error> {
         let url = self.get_url("/col/metadata");
 
         let resp = self.client
             .get(url.as_ref())
             .set("Accept", "application/json")
-            .set("user-agent", "CopyOnLanSvc")
-            .set("x-contract-version", "1")
-            .call()
-            .map_err(Box::new)?
-            .into_json::<models::Metadata>()?;
+            .set(ContractVersion::HEADER, &self.contract_version.to_string())
+            .call();
+
+        let resp = match resp {
+            Ok(resp) => resp,
+            Err(ureq::Error::Status(_, resp)) if resp.header(ContractVersion::HEADER).is_some() => {
+                let rejection = resp.into_json::<models::ContractVersionError>()?;
+                return Err(Error::UnsupportedContractVersion {
+                    requested: self.contract_version,
+                    supported: rejection.supported.into_iter().map(ContractVersion).collect(),
+                });
+            },
+            Err(e) => return Err(Box::new(e).into()),
+        };
 
-        Ok(resp)
+        let version = reported_contract_version(resp.header(ContractVersion::HEADER));
+        if let Some(version) = version.filter(|v| *v != self.contract_version) {
+            log::warn!("Requested contract version {}, server reported {version}", self.contract_version);
+        }
+
+        Ok((resp.into_json()?, version))
     }
 
     pub fn iterate_range(size: usize, step_size: usize) -> impl Iterator<Item = Range> {
         (0..size).step_by(step_size).map(move |offset| {
             let size = std::cmp::min(step_size, size - offset);
-            Range {
-                first_byte: offset,
-                last_byte: offset + size - 1
-            }
+            Range::from_len(offset, size).expect("Chunk is never empty")
         })
     }
 
     pub fn download_chunk(&self, path: &str, range: &Range) -> Result<ureq::Response, Error> {
+        self.download_chunk_of(path, range, &mut None)
+    }
+
+    /// Download `range` of the version with entity tag `etag`
+    ///
+    /// Without a tag yet, the one the server sends is kept. Later chunks send
+    /// it in `If-Range` and fail if the server answers with the full content,
+    /// as the item changed and the chunks would mix versions.
+    fn download_chunk_of(&self, path: &str, range: &Range, etag: &mut Option<String>) -> Result<ureq::Response, Error> {
         let url = self.get_url(path);
 
-        let resp = self.client
+        let wait = throttle::reserve_all(&self.rate_limiters, range.count());
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+
+        let request = self.client
             .get(url.as_ref())
-            .set("range", &format!("bytes={}-{}", range.first_byte, range.last_byte))
-            .call()
-            .map_err(Box::new)?;
+            .set("range", &range.to_string());
+        let request = match etag.as_deref() {
+            Some(etag) => request.set("if-range", etag),
+            None => request,
+        };
+        let resp = request.call().map_err(Box::new)?;
+
+        match etag {
+            Some(_) if resp.status() != 206 => {
+                return Err(Error::GeneralError(format!("{path} changed during download")));
+            }
+            Some(_) => {}
+            // Weak tags must not be used in If-Range
+            None => *etag = resp.header("etag").filter(|tag| !tag.starts_with("W/")).map(str::to_owned),
+        }
 
         Ok(resp)
     }
 
     pub fn get_item_filesize(&self, item: &models::MetadataItem) -> Result<usize, Error> {
-        let resp = self.download_chunk(&item.path, &Range::default())?;
-        log::trace!("{resp:?}");
-        let headers: Vec<String> = resp
-        .headers_names()
-        .into_iter()
-        .map(|k| {
-            let hdr_name = k.clone();
-            format!("{k}: {}", resp.header(hdr_name.as_ref()).unwrap_or("<NOT_SET>"))
-        }).collect();
-
-        dbg!(headers);
-        assert_eq!(resp.status(), 206, "Unexpected HTTP status, expected 206");
-
-        let content_length = match resp.header("content-range") {
-            Some(content_range) => {
-                let content_length = content_range.split('/')
-                    .last()
-                    .ok_or(Error::GeneralError("Failed to get full content length".to_owned()))?;
-
-                content_length.parse::<usize>()
-                    .map_err(|_|Error::GeneralError("Failed to parse content length".to_owned()))?
-            },
-            None => Err(Error::GeneralError("No content-range header returned".to_owned()))?,
+        Ok(self.stat(item)?.size)
+    }
+
+    /// Determine size and properties of an item
+    ///
+    /// Tries a `HEAD` request first, then a single byte range probe, and
+    /// falls back to `MetadataItem::size` if both fail. Range support is
+    /// taken from the probe, as consoles do not send `accept-ranges`. A size
+    /// disagreeing with the metadata is logged as a warning.
+    pub fn stat(&self, item: &models::MetadataItem) -> Result<ItemStat, Error> {
+        let head = self.stat_head(&item.path);
+        if let Some(stat) = head.as_ref().filter(|stat| stat.accepts_ranges) {
+            return Ok(self.check_stat_size(item, stat.clone()));
+        }
+
+        let probe = self.stat_probe(&item.path);
+        let stat = match (head, probe) {
+            (Some(head), Ok(Some(probe))) => ItemStat { accepts_ranges: probe.accepts_ranges, ..head },
+            (Some(head), _) => head,
+            (None, Ok(Some(probe))) => probe,
+            (None, probe) if item.size > 0 => {
+                if let Err(e) = probe {
+                    log::debug!("Range probe of {} failed, using metadata size: {e}", item.path);
+                }
+                ItemStat {
+                    size: item.size,
+                    source: SizeSource::Metadata,
+                    accepts_ranges: false,
+                    content_type: None,
+                    etag: None,
+                    last_modified: None,
+                }
+            }
+            (None, Err(e)) => Err(e)?,
+            (None, Ok(None)) => Err(Error::GeneralError(format!("Failed to determine size of {}", item.path)))?,
         };
 
-        Ok(content_length)
+        Ok(self.check_stat_size(item, stat))
+    }
+
+    fn check_stat_size(&self, item: &models::MetadataItem, stat: ItemStat) -> ItemStat {
+        if item.size != 0 && item.size != stat.size {
+            log::warn!(
+                "Size of {} from {:?} ({}) disagrees with metadata ({})",
+                item.package_family_name, stat.source, stat.size, item.size
+            );
+        }
+
+        stat
+    }
+
+    fn stat_head(&self, path: &str) -> Option<ItemStat> {
+        let resp = match self.client.head(self.get_url(path).as_ref()).call() {
+            Ok(resp) => resp,
+            Err(e) => {
+                log::debug!("HEAD {path} failed: {e}");
+                return None;
+            }
+        };
+        log::trace!("HEAD {path}: {:?}", Self::response_headers(&resp));
+
+        let size = resp.header("content-length")?.trim().parse().ok()?;
+        Some(ItemStat::from_response(&resp, size, SizeSource::Head))
+    }
+
+    fn stat_probe(&self, path: &str) -> Result<Option<ItemStat>, Error> {
+        let resp = self.download_chunk(path, &Range::single(0))?;
+        log::trace!("Probe {path}: {} {:?}", resp.status(), Self::response_headers(&resp));
+
+        let stat = match resp.status() {
+            206 => {
+                let content_range: range::ContentRange = resp.header("content-range")
+                    .ok_or(Error::GeneralError("No content-range header returned".to_owned()))?
+                    .parse()?;
+                content_range.total
+                    .map(|size| ItemStat::from_response(&resp, size, SizeSource::RangeProbe))
+            }
+            // Range ignored, the full content is on its way
+            200 => resp.header("content-length")
+                .and_then(|length| length.trim().parse().ok())
+                .map(|size| ItemStat {
+                    accepts_ranges: false,
+                    ..ItemStat::from_response(&resp, size, SizeSource::RangeProbe)
+                }),
+            status => Err(Error::GeneralError(format!("Unexpected HTTP status {status} probing {path}")))?,
+        };
+
+        Ok(stat)
+    }
+
+    fn response_headers(resp: &ureq::Response) -> Vec<String> {
+        resp.headers_names()
+            .into_iter()
+            .map(|name| format!("{name}: {}", resp.header(&name).unwrap_or_default()))
+            .collect()
     }
 
     pub fn download_chunks(&self, item: &models::MetadataItem, content_length: usize, writer: &mut impl std::io::Write, chunk_size: usize) -> Result<usize, Error>  {
-        dbg!(content_length);
+        self.download_chunks_from(item, 0, content_length, writer, chunk_size)
+    }
+
+    /// Download `offset..content_length`, e.g. to resume a partial file
+    ///
+    /// Only complete chunks are written, so a cancelled download leaves a
+    /// partial file that can be resumed. Returns the number of bytes written,
+    /// not counting `offset`.
+    pub fn download_chunks_from(&self, item: &models::MetadataItem, offset: usize, content_length: usize, writer: &mut impl std::io::Write, chunk_size: usize) -> Result<usize, Error>  {
+        let remaining = content_length.checked_sub(offset)
+            .ok_or(Error::GeneralError(format!("Offset {offset} beyond content length {content_length}")))?;
 
         let mut buf = vec![0u8; chunk_size];
-        let written: usize = Self::iterate_range(content_length, chunk_size).map(move |range|{
-            let resp = self.download_chunk(&item.path, &range).unwrap();
-            resp.into_reader().read_exact(&mut buf[..range.count()]).unwrap();
-            writer.write(&buf[..range.count()]).unwrap()
-        }).sum();
+        let mut written = 0;
+        let mut etag = None;
+        for range in Self::iterate_range(remaining, chunk_size) {
+            self.check_cancelled()?;
+            let range = range.offset(offset)?;
+            let resp = self.download_chunk_of(&item.path, &range, &mut etag)?;
+            resp.into_reader().read_exact(&mut buf[..range.count()])?;
+            writer.write_all(&buf[..range.count()])?;
+            written += range.count();
+        }
 
-        assert_eq!(written, content_length);
         Ok(written)
     }
-}
 
-struct Server;
+    /// Download `offset..content_length` with chunk sizes adapting to throughput
+    ///
+    /// Failed chunks are retried with a smaller size, giving up after
+    /// [`MAX_CHUNK_RETRIES`] consecutive failures. `progress` is called after
+    /// every chunk. Returns a [`TransferSummary`], its byte count excludes `offset`.
+    pub fn download_chunks_adaptive(&self, item: &models::MetadataItem, offset: usize, content_length: usize, writer: &mut impl std::io::Write, sizing: ChunkSizing, mut progress: impl FnMut(&Progress)) -> Result<TransferSummary, Error>  {
+        if offset > content_length {
+            return Err(Error::GeneralError(format!("Offset {offset} beyond content length {content_length}")));
+        }
+
+        let mut ranges = AdaptiveRanges::new(offset, content_length, sizing);
+        let mut buf = vec![];
+        let mut written = 0;
+        let mut failures = 0;
+        let mut retries = 0;
+        let mut chunks = 0;
+        let mut etag = None;
+        let mut peak_throughput: f64 = 0.0;
+        let transfer_started = std::time::Instant::now();
+        while let Some(range) = ranges.next() {
+            self.check_cancelled()?;
+            buf.resize(range.count(), 0);
+            let started = std::time::Instant::now();
+            let result = self.download_chunk_of(&item.path, &range, &mut etag)
+                .and_then(|resp| Ok(resp.into_reader().read_exact(&mut buf)?));
+
+            match result {
+                Ok(()) => failures = 0,
+                Err(e) if failures < MAX_CHUNK_RETRIES && is_retryable(&e) => {
+                    failures += 1;
+                    retries += 1;
+                    ranges.record_failure(&range);
+                    log::warn!("Chunk {range} failed ({e:?}), retrying with {} bytes", ranges.chunk_size());
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+
+            writer.write_all(&buf)?;
+            written += range.count();
+            let throughput = ranges.record_success(range.count(), started.elapsed());
+            chunks += 1;
+            peak_throughput = peak_throughput.max(throughput);
+            progress(&Progress {
+                written: offset + written,
+                total: content_length,
+                chunk_size: range.count(),
+                next_chunk_size: ranges.chunk_size(),
+                throughput,
+            });
+        }
 
-impl Server {
+        Ok(TransferSummary::new(
+            &self.console_name(),
+            &item.package_family_name,
+            written,
+            transfer_started.elapsed(),
+            peak_throughput,
+            retries,
+            chunks,
+        ))
+    }
+}
 
+/// Contract version a server reported, a malformed header counts as version 1
+fn reported_contract_version(header: Option<&str>) -> Option<ContractVersion> {
+    match header?.parse() {
+        Ok(version) => Some(version),
+        Err(e) => {
+            log::warn!("Ignoring malformed {} header {header:?}, assuming version 1: {e:?}", ContractVersion::HEADER);
+            Some(ContractVersion::V1)
+        }
+    }
 }
 
+/// Consecutive failed chunks tolerated by [`Client::download_chunks_adaptive`]
+pub const MAX_CHUNK_RETRIES: usize = 3;
+
+/// Timeouts, connection and server errors are worth another try, client errors are not
+fn is_retryable(error: &Error) -> bool {
+    match error {
+        Error::HttpError(e) => match e.as_ref() {
+            ureq::Error::Status(status, _) => *status >= 500,
+            ureq::Error::Transport(_) => true,
+        },
+        Error::IoError(_) => true,
+        _ => false,
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use std::net::Ipv4Addr;
 
     use super::*;
 
+    #[test]
+    fn test_reported_contract_version() {
+        assert_eq!(reported_contract_version(None), None);
+        assert_eq!(reported_contract_version(Some("1")), Some(ContractVersion::V1));
+        assert_eq!(reported_contract_version(Some("2")), Some(ContractVersion(2)));
+        assert_eq!(reported_contract_version(Some("one")), Some(ContractVersion::V1));
+    }
+
     #[test]
     fn test_generate_console_id() {
         let cid = generate_random_console_id();
@@ -303,13 +897,51 @@ mod tests {
         assert_eq!(info.get_property_val_str("U"), Some("X92348235235"));
     }
 
+    #[test]
+    fn test_content_path() {
+        let path = "/col/content/%7BA89ECE52-7E8E-444F-BBD0-C68B76C2ECA4%7D%2311032Reconco.XboxControllerTester_thvmwcgtjwwvy";
+        let content = ContentPath::from_url_path(path).expect("Failed parsing content path");
+        assert_eq!(content.drive_id, Uuid::parse_str("a89ece52-7e8e-444f-bbd0-c68b76c2eca4").unwrap());
+        assert_eq!(content.name, "11032Reconco.XboxControllerTester_thvmwcgtjwwvy");
+        assert_eq!(content.to_url_path(), path);
+
+        let decoded: ContentPath = "{A89ECE52-7E8E-444F-BBD0-C68B76C2ECA4}#some file.xvc".parse().unwrap();
+        assert!(decoded.to_url_path().ends_with("%23some%20file.xvc"));
+        assert!("no-drive-id".parse::<ContentPath>().is_err());
+    }
+
+    #[test]
+    fn test_contract_version() {
+        assert_eq!(ContractVersion::default(), ContractVersion::V1);
+        assert_eq!(" 2".parse::<ContractVersion>().unwrap(), ContractVersion(2));
+        assert_eq!(ContractVersion(3).to_string(), "3");
+        assert!("v1".parse::<ContractVersion>().is_err());
+        assert!(ContractVersion::V1.is_supported());
+        assert!(!ContractVersion(2).is_supported());
+    }
+
+    #[test]
+    fn test_cancelled_download() {
+        let token = CancellationToken::new();
+        let client = Client::builder("127.0.0.1", 1)
+            .cancellation_token(token.clone())
+            .build();
+        token.cancel();
+
+        let item = models::MetadataItem { path: "/col/content/item".into(), ..Default::default() };
+        let mut written = vec![];
+        let result = client.download_chunks_from(&item, 0, 100, &mut written, 10);
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert!(written.is_empty());
+    }
+
     #[test]
     fn test_range_iterator() {
         let mut it1 = Client::iterate_range(4200, 1024);
-        assert_eq!(Range::new(0,1023), it1.next().unwrap());
-        assert_eq!(Range::new(1024,2047), it1.next().unwrap());
-        assert_eq!(Range::new(2048,3071), it1.next().unwrap());
-        assert_eq!(Range::new(3072,4095), it1.next().unwrap());
-        assert_eq!(Range::new(4096,4199), it1.next().unwrap());
+        assert_eq!(Range::new(0,1023).unwrap(), it1.next().unwrap());
+        assert_eq!(Range::new(1024,2047).unwrap(), it1.next().unwrap());
+        assert_eq!(Range::new(2048,3071).unwrap(), it1.next().unwrap());
+        assert_eq!(Range::new(3072,4095).unwrap(), it1.next().unwrap());
+        assert_eq!(Range::new(4096,4199).unwrap(), it1.next().unwrap());
     }
 }
@@ -0,0 +1,337 @@
+//! Access logging and server metrics.
+//!
+//! Every request is logged once its response body is done or dropped, with
+//! target `access` and `key=value` fields. [`Metrics`] keeps the counters
+//! exposed in Prometheus text format on the admin port, see
+//! [`crate::admin`], and the progress of every transfer in flight.
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use serde::Serialize;
+
+use axum::{
+    body::Bytes,
+    http::{header, Request, Response},
+};
+use http_body::Body;
+
+use crate::ContentPath;
+
+/// Response extension naming the item a content response serves
+///
+/// Responses count as transfers of this item, keyed by its canonical path
+/// so aliases of an item do not add series, see
+/// [`crate::provider::ContentProvider::canonical_path`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServedItem(pub ContentPath);
+
+/// Content response being sent
+#[derive(Debug)]
+struct Transfer {
+    client: SocketAddr,
+    item: String,
+    range: Option<String>,
+    length: Option<u64>,
+    start: Instant,
+    sent: Arc<AtomicU64>,
+}
+
+/// Progress of a content response being sent
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferStatus {
+    pub client: SocketAddr,
+    pub item: String,
+    pub range: Option<String>,
+    /// Bytes sent so far
+    pub sent: u64,
+    /// Bytes of the response body, if known
+    pub length: Option<u64>,
+    pub elapsed_secs: f64,
+}
+
+/// Counters of a running server
+#[derive(Debug, Default)]
+pub struct Metrics {
+    active_transfers: AtomicU64,
+    requests: Mutex<BTreeMap<u16, u64>>,
+    bytes_served: Mutex<BTreeMap<String, u64>>,
+    next_transfer: AtomicU64,
+    transfers: Mutex<BTreeMap<u64, Transfer>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Content responses currently being sent
+    pub fn active_transfers(&self) -> u64 {
+        self.active_transfers.load(Ordering::Relaxed)
+    }
+
+    /// Content responses currently being sent, oldest first
+    pub fn transfers(&self) -> Vec<TransferStatus> {
+        let transfers = self.transfers.lock().unwrap_or_else(|e| e.into_inner());
+        transfers
+            .values()
+            .map(|transfer| TransferStatus {
+                client: transfer.client,
+                item: transfer.item.clone(),
+                range: transfer.range.clone(),
+                sent: transfer.sent.load(Ordering::Relaxed),
+                length: transfer.length,
+                elapsed_secs: transfer.start.elapsed().as_secs_f64(),
+            })
+            .collect()
+    }
+
+    /// Requests answered with `status`
+    pub fn requests(&self, status: u16) -> u64 {
+        let requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        requests.get(&status).copied().unwrap_or_default()
+    }
+
+    /// Content bytes sent of the item at `path`
+    pub fn bytes_served(&self, path: &str) -> u64 {
+        let bytes_served = self.bytes_served.lock().unwrap_or_else(|e| e.into_inner());
+        bytes_served.get(path).copied().unwrap_or_default()
+    }
+
+    fn record_request(&self, status: u16) {
+        let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        *requests.entry(status).or_default() += 1;
+    }
+
+    fn start_transfer(&self, transfer: Transfer) -> u64 {
+        self.active_transfers.fetch_add(1, Ordering::Relaxed);
+        let id = self.next_transfer.fetch_add(1, Ordering::Relaxed);
+        self.transfers.lock().unwrap_or_else(|e| e.into_inner()).insert(id, transfer);
+        id
+    }
+
+    fn finish_transfer(&self, id: u64) {
+        self.active_transfers.fetch_sub(1, Ordering::Relaxed);
+        self.transfers.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+    }
+
+    fn record_bytes(&self, path: &str, bytes: u64) {
+        let mut bytes_served = self.bytes_served.lock().unwrap_or_else(|e| e.into_inner());
+        *bytes_served.entry(path.to_owned()).or_default() += bytes;
+    }
+
+    /// Prometheus text exposition of all counters
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String does not fail
+        let _ = writeln!(out, "# HELP network_transfer_active_transfers Content responses currently being sent.");
+        let _ = writeln!(out, "# TYPE network_transfer_active_transfers gauge");
+        let _ = writeln!(out, "network_transfer_active_transfers {}", self.active_transfers());
+
+        let _ = writeln!(out, "# HELP network_transfer_requests_total Requests answered, by status code.");
+        let _ = writeln!(out, "# TYPE network_transfer_requests_total counter");
+        for (status, count) in self.requests.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+            let _ = writeln!(out, "network_transfer_requests_total{{status=\"{status}\"}} {count}");
+        }
+
+        let _ = writeln!(out, "# HELP network_transfer_bytes_served_total Content bytes sent, by item path.");
+        let _ = writeln!(out, "# TYPE network_transfer_bytes_served_total counter");
+        for (path, bytes) in self.bytes_served.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+            let _ = writeln!(out, "network_transfer_bytes_served_total{{item=\"{}\"}} {bytes}", escape_label(path));
+        }
+
+        out
+    }
+}
+
+/// Escape a Prometheus label value
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// A request whose access log line is written once its response is sent
+pub struct AccessEntry {
+    metrics: Arc<Metrics>,
+    client: SocketAddr,
+    method: String,
+    path: String,
+    range: Option<String>,
+    status: Option<u16>,
+    item: Option<String>,
+    transfer: Option<u64>,
+    start: Instant,
+    sent: Arc<AtomicU64>,
+}
+
+impl AccessEntry {
+    /// Start logging `request` from `client`, answered later via [`AccessEntry::respond`]
+    pub fn new<B>(metrics: Arc<Metrics>, client: SocketAddr, request: &Request<B>) -> Self {
+        let path = request.uri().path();
+        let range = request
+            .headers()
+            .get(header::RANGE)
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+
+        Self {
+            metrics,
+            client,
+            method: request.method().to_string(),
+            path: path.to_owned(),
+            range,
+            status: None,
+            item: None,
+            transfer: None,
+            start: Instant::now(),
+            sent: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Record the response, successful content responses count as a
+    /// transfer of the item until the entry is dropped
+    pub fn respond<B>(&mut self, response: &Response<B>) {
+        let status = response.status();
+        self.metrics.record_request(status.as_u16());
+        self.status = Some(status.as_u16());
+        self.item = match status.is_success() {
+            true => response.extensions().get::<ServedItem>().map(|ServedItem(item)| item.to_url_path()),
+            false => None,
+        };
+
+        if let Some(item) = &self.item {
+            let length = response
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok()?.parse().ok());
+            self.transfer = Some(self.metrics.start_transfer(Transfer {
+                client: self.client,
+                item: item.clone(),
+                range: self.range.clone(),
+                length,
+                start: self.start,
+                sent: self.sent.clone(),
+            }));
+        }
+    }
+}
+
+impl Drop for AccessEntry {
+    fn drop(&mut self) {
+        let sent = self.sent.load(Ordering::Relaxed);
+        if let (Some(item), Some(transfer)) = (&self.item, self.transfer) {
+            self.metrics.finish_transfer(transfer);
+            self.metrics.record_bytes(item, sent);
+        }
+
+        log::info!(
+            target: "access",
+            "client={} method={} path={} range={} status={} bytes={} duration_ms={}",
+            self.client.ip(),
+            self.method,
+            self.path,
+            self.range.as_deref().unwrap_or("-"),
+            self.status.map(|status| status.to_string()).as_deref().unwrap_or("-"),
+            sent,
+            self.start.elapsed().as_millis(),
+        );
+    }
+}
+
+/// Response body counting the bytes sent for its [`AccessEntry`]
+pub struct LoggedBody<B> {
+    inner: B,
+    entry: AccessEntry,
+}
+
+impl<B> LoggedBody<B> {
+    pub fn new(inner: B, entry: AccessEntry) -> Self {
+        Self { inner, entry }
+    }
+}
+
+impl<B> Body for LoggedBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let polled = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &polled {
+            self.entry.sent.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }
+
+        polled
+    }
+
+    fn poll_trailers(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<axum::http::HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        let metrics = Arc::new(Metrics::new());
+        let client: SocketAddr = "192.168.1.2:50000".parse().unwrap();
+        let content_path = ContentPath::new(uuid::Uuid::nil(), "a\"b.appx");
+        let item = content_path.to_url_path();
+        let request = |path: &str| Request::get(path).header("range", "bytes=0-9").body(()).unwrap();
+
+        let response = |status: StatusCode| Response::builder().status(status).header("content-length", 10).body(()).unwrap();
+
+        // Requested under another drive id, counted under the canonical path
+        let mut transfer = AccessEntry::new(metrics.clone(), client, &request(&ContentPath::new(uuid::Uuid::new_v4(), "a\"b.appx").to_url_path()));
+        let mut served = response(StatusCode::PARTIAL_CONTENT);
+        served.extensions_mut().insert(ServedItem(content_path));
+        transfer.respond(&served);
+        let mut missing = AccessEntry::new(metrics.clone(), client, &request("/col/content/%7B00000000-0000-0000-0000-000000000000%7D%23missing"));
+        missing.respond(&response(StatusCode::NOT_FOUND));
+        drop(missing);
+        let mut listing = AccessEntry::new(metrics.clone(), client, &request("/col/metadata"));
+        listing.respond(&response(StatusCode::OK));
+        drop(listing);
+        assert_eq!(metrics.active_transfers(), 1);
+
+        transfer.sent.store(4, Ordering::Relaxed);
+        let transfers = metrics.transfers();
+        assert_eq!(transfers.len(), 1);
+        assert_eq!((transfers[0].item.as_str(), transfers[0].sent, transfers[0].length), (item.as_str(), 4, Some(10)));
+        assert_eq!(transfers[0].range.as_deref(), Some("bytes=0-9"));
+
+        transfer.sent.store(10, Ordering::Relaxed);
+        drop(transfer);
+        assert!(metrics.transfers().is_empty());
+        assert_eq!(metrics.active_transfers(), 0);
+        assert_eq!(metrics.bytes_served(&item), 10);
+        assert_eq!((metrics.requests(206), metrics.requests(404), metrics.requests(200)), (1, 1, 1));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("network_transfer_active_transfers 0\n"));
+        assert!(rendered.contains("network_transfer_requests_total{status=\"206\"} 1\n"));
+        assert!(rendered.contains(&format!("network_transfer_bytes_served_total{{item=\"{item}\"}} 10\n")));
+    }
+}
@@ -1,31 +1,105 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Item listed in `/col/metadata`
+///
+/// Only `path` is required, any other missing field falls back to its default
+/// so a firmware dropping a field does not break the whole list. Unknown
+/// fields are kept in `extra` and serialized again as they came in.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MetadataItem {
-    #[serde(rename = "type")]
+    #[serde(rename = "type", default)]
     pub typ: String,
+    #[serde(default)]
     pub has_content_id: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub is_xvc: Option<bool>,
+    #[serde(default)]
     pub content_id: String,
+    #[serde(default)]
     pub product_id: String,
+    #[serde(default)]
     pub package_family_name: String,
+    #[serde(default)]
     pub one_store_product_id: String,
+    #[serde(default)]
     pub version: String,
+    #[serde(default)]
     pub size: usize,
+    #[serde(default)]
     pub allowed_product_id: String,
+    #[serde(default)]
     pub allowed_package_family_name: String,
     pub path: String,
+    #[serde(default)]
     pub availability: String,
+    #[serde(default)]
     pub generation: String,
+    #[serde(default)]
     pub related_media: Vec<String>,
+    #[serde(default)]
     pub related_media_family_names: Vec<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Metadata {
     pub items: Vec<MetadataItem>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Item left out by [`Metadata::from_value_lenient`]
+#[derive(Debug, Clone)]
+pub struct SkippedItem {
+    pub index: usize,
+    pub error: String,
+    pub raw: Value,
+}
+
+impl Metadata {
+    /// Parse metadata, skipping items that fail to deserialize
+    ///
+    /// Only fails if the document itself is malformed, e.g. has no `items`
+    /// array. Skipped items are returned along with their error.
+    pub fn from_value_lenient(value: Value) -> Result<(Self, Vec<SkippedItem>), serde_json::Error> {
+        #[derive(Deserialize)]
+        struct RawMetadata {
+            items: Vec<Value>,
+            #[serde(flatten)]
+            extra: Map<String, Value>,
+        }
+
+        let raw: RawMetadata = serde_json::from_value(value)?;
+        let mut metadata = Metadata {
+            items: Vec::with_capacity(raw.items.len()),
+            extra: raw.extra,
+        };
+        let mut skipped = vec![];
+        for (index, item) in raw.items.into_iter().enumerate() {
+            match MetadataItem::deserialize(&item) {
+                Ok(parsed) => metadata.items.push(parsed),
+                Err(e) => skipped.push(SkippedItem {
+                    index,
+                    error: e.to_string(),
+                    raw: item,
+                }),
+            }
+        }
+
+        Ok((metadata, skipped))
+    }
+}
+
+/// Body returned with `400 Bad Request` for an unsupported `x-contract-version`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContractVersionError {
+    pub error: String,
+    pub requested: String,
+    pub supported: Vec<u32>,
 }
 
 #[cfg(test)]
@@ -77,5 +151,36 @@ mod tests {
         assert_eq!(first.generation, "uwpgen9");
         assert!(first.related_media.is_empty());
         assert!(first.related_media_family_names.is_empty());
+        assert!(first.extra.is_empty());
+    }
+
+    #[test]
+    fn tolerate_missing_and_unknown_fields() {
+        let json = r#"{"items":[{"type":"game","path":"/col/content/a","size":42,"newField":{"nested":true}}],"cursor":"abc"}"#;
+
+        let metadata = serde_json::from_str::<Metadata>(json).expect("Failed deserializing");
+        let item = &metadata.items[0];
+        assert_eq!(item.typ, "game");
+        assert_eq!(item.size, 42);
+        assert!(item.related_media.is_empty());
+        assert_eq!(item.extra["newField"]["nested"], true);
+        assert_eq!(metadata.extra["cursor"], "abc");
+
+        let reserialized: Value = serde_json::to_value(&metadata).unwrap();
+        assert_eq!(reserialized["items"][0]["newField"]["nested"], true);
+        assert_eq!(reserialized["cursor"], "abc");
+    }
+
+    #[test]
+    fn lenient_skips_bad_items() {
+        let json = r#"{"items":[{"path":"/col/content/a"},{"type":"app"},{"path":"/col/content/c","size":"big"}]}"#;
+        assert!(serde_json::from_str::<Metadata>(json).is_err());
+
+        let (metadata, skipped) = Metadata::from_value_lenient(serde_json::from_str(json).unwrap()).unwrap();
+        assert_eq!(metadata.items.len(), 1);
+        assert_eq!(skipped.iter().map(|s| s.index).collect::<Vec<_>>(), [1, 2]);
+        assert!(skipped[0].error.contains("path"));
+
+        assert!(Metadata::from_value_lenient(serde_json::json!({"things": []})).is_err());
     }
 }
\ No newline at end of file
@@ -0,0 +1,696 @@
+//! Content backends for the server.
+//!
+//! A [`ContentProvider`] lists the items offered in `/col/metadata` and
+//! opens byte ranges of them for `/col/content/...`. [`FileSystemProvider`]
+//! serves local files, including split and archived packages,
+//! [`MemoryProvider`] serves buffers held in memory.
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use uuid::Uuid;
+
+use crate::{
+    cache::MetadataDiff,
+    error::Error,
+    models::{Metadata, MetadataItem},
+    push::{sideload_metadata, PushItem},
+    vfs::{is_tar, split_base, FileStamp, VirtualFile, FIRST_PART},
+    ContentPath, Range,
+};
+
+/// Opened byte range of an item
+pub struct Content {
+    /// Reader positioned at the start of the range, ending with it
+    pub reader: Box<dyn AsyncRead + Send + Unpin>,
+    /// Total size of the item
+    pub size: usize,
+}
+
+#[async_trait]
+pub trait ContentProvider: Send + Sync {
+    /// Items offered in `/col/metadata`
+    async fn list(&self) -> Result<Metadata, Error>;
+
+    /// Total size of the item, [`Error::NotFound`] for unknown paths
+    async fn size(&self, path: &ContentPath) -> Result<usize, Error>;
+
+    /// Open `range` of the item, [`Error::NotFound`] for unknown paths
+    async fn open(&self, path: &ContentPath, range: Range) -> Result<Content, Error>;
+
+    /// Entity tag of the item's current version, if versions are told apart
+    ///
+    /// Sent as `ETag`, ranges requested with a stale `If-Range` are answered
+    /// with the full current content instead.
+    async fn etag(&self, _path: &ContentPath) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+
+    /// Path of the item `path` resolves to, the key metrics and push
+    /// progress are recorded under, `path` itself unless aliases resolve to
+    /// the same item
+    fn canonical_path(&self, path: &ContentPath) -> ContentPath {
+        path.clone()
+    }
+
+    /// Offer the local file `file` as well, returns the items added
+    async fn add_file(&self, file: &Path) -> Result<Vec<MetadataItem>, Error> {
+        Err(Error::GeneralError(format!("Provider cannot add {file:?}")))
+    }
+
+    /// Stop offering the item at `path`, [`Error::NotFound`] for unknown paths
+    async fn remove_item(&self, path: &ContentPath) -> Result<MetadataItem, Error> {
+        Err(Error::GeneralError(format!("Provider cannot remove {}", path.to_url_path())))
+    }
+}
+
+/// Package assembled from split parts or a tar entry
+#[derive(Debug)]
+struct Package {
+    content_path: ContentPath,
+    item: MetadataItem,
+    file: VirtualFile,
+}
+
+impl Package {
+    fn new(content_path: ContentPath, file: VirtualFile) -> Self {
+        let name = content_path.name.as_str();
+        let family_name = Path::new(name).file_stem().and_then(|stem| stem.to_str()).unwrap_or(name);
+        let item = sideload_metadata(&content_path, family_name, file.size());
+
+        Self {
+            content_path,
+            item,
+            file,
+        }
+    }
+}
+
+/// Where the bytes of an item are stored
+enum Source {
+    /// Local file, stamped as listed unless served from the root by name
+    File(PathBuf, Option<FileStamp>),
+    Virtual(VirtualFile),
+}
+
+/// Items offered by a [`FileSystemProvider`]
+#[derive(Debug, Default)]
+struct Library {
+    items: Vec<PushItem>,
+    packages: Vec<Package>,
+    /// Added at runtime, kept across reloads
+    added: Vec<PathBuf>,
+    /// Removed at runtime, not served from the root directory either
+    removed: Vec<ContentPath>,
+}
+
+impl Library {
+    /// Items for every file in `dir`, files failing to load are skipped
+    fn scan(dir: &Path, drive_id: Uuid) -> Result<Self, Error> {
+        let mut files = vec![];
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() && !is_later_part(&path) {
+                files.push(path);
+            }
+        }
+        files.sort();
+
+        let mut library = Self::default();
+        for path in files {
+            match Self::load(&path, drive_id) {
+                Ok(loaded) => {
+                    library.insert(loaded);
+                }
+                Err(e) => log::warn!("Skipping {path:?}: {e:?}"),
+            }
+        }
+
+        Ok(library)
+    }
+
+    fn metadata(&self) -> Metadata {
+        let items = self.items.iter().map(|item| item.item.clone());
+        let packages = self.packages.iter().map(|package| package.item.clone());
+        Metadata {
+            items: items.chain(packages).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Items of the local file `file`, split files and tar archives as their packages
+    ///
+    /// Reads the file system only, so it can run before the library is locked.
+    fn load(file: &Path, drive_id: Uuid) -> Result<Self, Error> {
+        let packages = if let Some(base) = split_base(file) {
+            vec![Package::new(ContentPath::new(drive_id, base), VirtualFile::split(file)?)]
+        } else if is_tar(file) {
+            VirtualFile::tar_entries(file)?
+                .into_iter()
+                .map(|(name, package)| Package::new(ContentPath::new(drive_id, &name), package))
+                .collect()
+        } else {
+            return Ok(Self {
+                items: vec![PushItem::from_file(file, drive_id)?],
+                ..Default::default()
+            });
+        };
+
+        Ok(Self {
+            packages,
+            ..Default::default()
+        })
+    }
+
+    /// Offer the items of `loaded`, replacing items at the same paths
+    fn insert(&mut self, loaded: Library) -> Vec<MetadataItem> {
+        let added = loaded.metadata().items;
+        for item in loaded.items {
+            self.remove(&item.content_path);
+            self.removed.retain(|removed| removed != &item.content_path);
+            self.items.push(item);
+        }
+        for package in loaded.packages {
+            self.remove(&package.content_path);
+            self.removed.retain(|removed| removed != &package.content_path);
+            self.packages.push(package);
+        }
+
+        added
+    }
+
+    fn remove(&mut self, path: &ContentPath) -> Option<MetadataItem> {
+        if let Some(idx) = self.items.iter().position(|item| &item.content_path == path) {
+            return Some(self.items.remove(idx).item);
+        }
+        let idx = self.packages.iter().position(|package| &package.content_path == path)?;
+        Some(self.packages.remove(idx).item)
+    }
+}
+
+/// Local files, optionally falling back to any file in a directory by name
+///
+/// Split files (`name.001`, `name.002`, ...) and tar archive entries are
+/// offered as single packages, see [`VirtualFile`]. Items can be added and
+/// removed while serving.
+#[derive(Debug)]
+pub struct FileSystemProvider {
+    library: RwLock<Library>,
+    root: Option<PathBuf>,
+    drive_id: Uuid,
+}
+
+impl FileSystemProvider {
+    /// Offer `items`, files added later get the drive id of the first item
+    pub fn new(items: Vec<PushItem>) -> Self {
+        let drive_id = items.first().map(|item| item.content_path.drive_id).unwrap_or_else(Uuid::new_v4);
+        Self {
+            library: RwLock::new(Library {
+                items,
+                ..Default::default()
+            }),
+            root: None,
+            drive_id,
+        }
+    }
+
+    /// Offer every file in `dir`, other names requested are looked up in `dir` too
+    ///
+    /// Split files are offered once under their name without the part
+    /// number, tar archives by the names of their entries. Files failing to
+    /// load, e.g. while still being copied, are skipped until reloaded.
+    pub fn from_dir(dir: impl AsRef<Path>, drive_id: Uuid) -> Result<Self, Error> {
+        let dir = dir.as_ref();
+        Ok(Self {
+            library: RwLock::new(Library::scan(dir, drive_id)?),
+            root: None,
+            drive_id,
+        }
+        .with_root(dir))
+    }
+
+    /// Directory served, if any
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    /// Rescan the root directory and swap the library in one step
+    ///
+    /// Items added or removed at runtime stay so. Changed items get a new
+    /// entity tag, so a chunked download sending the old one in `If-Range`
+    /// is answered with the full new content rather than a range of it,
+    /// instead of mixing both versions.
+    pub fn reload(&self) -> Result<MetadataDiff, Error> {
+        let root = self.root.as_ref().ok_or(Error::GeneralError("No library directory to reload".into()))?;
+        let mut scanned = Library::scan(root, self.drive_id)?;
+
+        // Files added at runtime are loaded before locking, again if more were added meanwhile
+        let (mut library, loaded) = loop {
+            let added = self.library().added.clone();
+            let loaded: Vec<_> = added
+                .iter()
+                .filter_map(|file| match Library::load(file, self.drive_id) {
+                    Ok(loaded) => Some(loaded),
+                    Err(e) => {
+                        log::warn!("Dropping {file:?} added earlier: {e:?}");
+                        None
+                    }
+                })
+                .collect();
+
+            let library = self.library.write().unwrap_or_else(|e| e.into_inner());
+            if library.added == added {
+                break (library, loaded);
+            }
+        };
+        for loaded in loaded {
+            scanned.insert(loaded);
+        }
+        for path in &library.removed {
+            scanned.remove(path);
+        }
+        scanned.added = std::mem::take(&mut library.added);
+        scanned.removed = std::mem::take(&mut library.removed);
+
+        let diff = MetadataDiff::between(&library.metadata(), &scanned.metadata());
+        *library = scanned;
+
+        Ok(diff)
+    }
+
+    /// Serve content paths not offered by name from `dir`
+    pub fn with_root(mut self, dir: impl AsRef<Path>) -> Self {
+        self.root = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Offer `file` as a sideloaded app at `content_path`
+    pub fn with_package(mut self, content_path: ContentPath, file: VirtualFile) -> Self {
+        let library = self.library.get_mut().unwrap_or_else(|e| e.into_inner());
+        library.remove(&content_path);
+        library.packages.push(Package::new(content_path, file));
+        self
+    }
+
+    /// Plain files offered, packages not included
+    pub fn items(&self) -> Vec<PushItem> {
+        self.library().items.clone()
+    }
+
+    fn library(&self) -> std::sync::RwLockReadGuard<'_, Library> {
+        self.library.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn source(&self, path: &ContentPath) -> Result<Source, Error> {
+        let library = self.library();
+        if let Some(item) = library.items.iter().find(|item| &item.content_path == path) {
+            return Ok(Source::File(item.file.clone(), Some(item.stamp)));
+        }
+        if let Some(package) = library.packages.iter().find(|package| &package.content_path == path) {
+            return Ok(Source::Virtual(package.file.clone()));
+        }
+        // The root answers by name under any drive id, so must removals
+        if library.removed.iter().any(|removed| removed.name == path.name) {
+            return Err(Error::NotFound(path.to_url_path()));
+        }
+
+        // Names must not escape the root directory
+        let plain_name = !path.name.is_empty()
+            && !path.name.contains(['/', '\\'])
+            && path.name != ".."
+            && path.name != ".";
+        match &self.root {
+            Some(root) if plain_name => {
+                let file = root.join(&path.name);
+                let first_part = root.join(format!("{}.{FIRST_PART}", path.name));
+                match !file.exists() && first_part.is_file() {
+                    true => Ok(Source::Virtual(VirtualFile::split(first_part)?)),
+                    false => Ok(Source::File(file, None)),
+                }
+            }
+            _ => Err(Error::NotFound(path.to_url_path())),
+        }
+    }
+}
+
+/// Whether `path` is a part after the first of a split file
+fn is_later_part(path: &Path) -> bool {
+    let numbered = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extension != FIRST_PART && extension.len() == FIRST_PART.len() && extension.bytes().all(|b| b.is_ascii_digit())
+        });
+    numbered && path.with_extension(FIRST_PART).is_file()
+}
+
+/// Map a missing file to [`Error::NotFound`]
+fn not_found(path: &ContentPath) -> impl FnOnce(std::io::Error) -> Error + '_ {
+    move |e| match e.kind() {
+        std::io::ErrorKind::NotFound => Error::NotFound(path.to_url_path()),
+        _ => e.into(),
+    }
+}
+
+#[async_trait]
+impl ContentProvider for FileSystemProvider {
+    async fn list(&self) -> Result<Metadata, Error> {
+        Ok(self.library().metadata())
+    }
+
+    /// Files served from the root by name answer under any drive id, they
+    /// resolve to the provider's own
+    fn canonical_path(&self, path: &ContentPath) -> ContentPath {
+        let library = self.library();
+        let listed = library.items.iter().any(|item| &item.content_path == path)
+            || library.packages.iter().any(|package| &package.content_path == path);
+        match listed {
+            true => path.clone(),
+            false => ContentPath::new(self.drive_id, &path.name),
+        }
+    }
+
+    /// Listed items report their listed size, consistent with the metadata
+    async fn size(&self, path: &ContentPath) -> Result<usize, Error> {
+        let file = match self.source(path)? {
+            Source::File(_, Some(listed)) => return Ok(listed.len as usize),
+            Source::File(file, None) => file,
+            Source::Virtual(file) => return Ok(file.size()),
+        };
+        let metadata = tokio::fs::metadata(file).await.map_err(not_found(path))?;
+        match metadata.is_file() {
+            true => Ok(metadata.len() as usize),
+            false => Err(Error::NotFound(path.to_url_path())),
+        }
+    }
+
+    /// Files and packages listed as one version, that version or nothing
+    async fn etag(&self, path: &ContentPath) -> Result<Option<String>, Error> {
+        let stamp = match self.source(path)? {
+            Source::File(_, Some(listed)) => listed,
+            Source::File(file, None) => FileStamp::of(&tokio::fs::metadata(file).await.map_err(not_found(path))?),
+            Source::Virtual(file) => return Ok(Some(file.etag())),
+        };
+        Ok(Some(stamp.etag()))
+    }
+
+    /// Fails if a listed file changed on disk, until the library is reloaded
+    async fn open(&self, path: &ContentPath, range: Range) -> Result<Content, Error> {
+        let (file, listed) = match self.source(path)? {
+            Source::File(file, listed) => (file, listed),
+            Source::Virtual(file) => {
+                return Ok(Content {
+                    reader: file.open(range).await?,
+                    size: file.size(),
+                })
+            }
+        };
+        let mut file = tokio::fs::File::open(file).await.map_err(not_found(path))?;
+        let stamp = FileStamp::of(&file.metadata().await?);
+        let size = stamp.len as usize;
+        if listed.is_some_and(|listed| listed != stamp) {
+            return Err(Error::GeneralError(format!("{} changed on disk, not yet reloaded", path.to_url_path())));
+        }
+        file.seek(SeekFrom::Start(range.first() as u64)).await?;
+
+        Ok(Content {
+            reader: Box::new(file.take(range.count() as u64)),
+            size,
+        })
+    }
+
+    /// Only files inside the root directory can be added
+    async fn add_file(&self, file: &Path) -> Result<Vec<MetadataItem>, Error> {
+        let root = self.root.clone().ok_or(Error::GeneralError("No library directory to add files from".into()))?;
+
+        // Load without holding the lock, readers must not wait for disk I/O
+        let (path, drive_id) = (file.to_path_buf(), self.drive_id);
+        let (file, loaded) = tokio::task::spawn_blocking(move || {
+            // Resolved, so neither `..` nor symlinks lead out of the library
+            let (root, file) = (root.canonicalize()?, path.canonicalize()?);
+            if !file.starts_with(&root) {
+                return Err(Error::GeneralError(format!("{path:?} is outside the library directory")));
+            }
+            Library::load(&file, drive_id).map(|loaded| (file, loaded))
+        })
+        .await
+        .map_err(|e| Error::GeneralError(format!("Failed loading {file:?}: {e}")))??;
+
+        let mut library = self.library.write().unwrap_or_else(|e| e.into_inner());
+        library.added.push(file);
+        Ok(library.insert(loaded))
+    }
+
+    async fn remove_item(&self, path: &ContentPath) -> Result<MetadataItem, Error> {
+        let mut library = self.library.write().unwrap_or_else(|e| e.into_inner());
+        let removed = library.remove(path).ok_or_else(|| Error::NotFound(path.to_url_path()))?;
+        library.removed.push(path.clone());
+        Ok(removed)
+    }
+}
+
+#[derive(Debug)]
+struct MemoryItem {
+    content_path: ContentPath,
+    item: MetadataItem,
+    data: Bytes,
+}
+
+/// Items held in memory, can be changed while serving
+#[derive(Debug)]
+pub struct MemoryProvider {
+    drive_id: Uuid,
+    items: RwLock<Vec<MemoryItem>>,
+}
+
+impl Default for MemoryProvider {
+    fn default() -> Self {
+        Self::new(Uuid::new_v4())
+    }
+}
+
+impl MemoryProvider {
+    pub fn new(drive_id: Uuid) -> Self {
+        Self {
+            drive_id,
+            items: RwLock::new(vec![]),
+        }
+    }
+
+    /// Offer `data` as a sideloaded app named `name`, replacing an item of the same name
+    pub fn insert(&self, name: &str, data: impl Into<Bytes>) -> MetadataItem {
+        let data = data.into();
+        let content_path = ContentPath::new(self.drive_id, name);
+        let family_name = Path::new(name).file_stem().and_then(|stem| stem.to_str()).unwrap_or(name);
+        let item = sideload_metadata(&content_path, family_name, data.len());
+
+        self.store(content_path, item.clone(), data);
+        item
+    }
+
+    /// Offer `data` described by `item`, its path is the key
+    pub fn insert_item(&self, item: MetadataItem, data: impl Into<Bytes>) -> Result<(), Error> {
+        let content_path = ContentPath::from_url_path(&item.path)?;
+        self.store(content_path, item, data.into());
+
+        Ok(())
+    }
+
+    fn store(&self, content_path: ContentPath, item: MetadataItem, data: Bytes) {
+        let mut items = self.items.write().unwrap_or_else(|e| e.into_inner());
+        items.retain(|existing| existing.content_path != content_path);
+        items.push(MemoryItem {
+            content_path,
+            item,
+            data,
+        });
+    }
+
+    pub fn remove(&self, path: &ContentPath) -> Option<MetadataItem> {
+        let mut items = self.items.write().unwrap_or_else(|e| e.into_inner());
+        let idx = items.iter().position(|item| &item.content_path == path)?;
+        Some(items.remove(idx).item)
+    }
+
+    fn data(&self, path: &ContentPath) -> Result<Bytes, Error> {
+        self.items
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|item| &item.content_path == path)
+            .map(|item| item.data.clone())
+            .ok_or_else(|| Error::NotFound(path.to_url_path()))
+    }
+}
+
+#[async_trait]
+impl ContentProvider for MemoryProvider {
+    async fn list(&self) -> Result<Metadata, Error> {
+        let items = self.items.read().unwrap_or_else(|e| e.into_inner());
+        Ok(Metadata {
+            items: items.iter().map(|item| item.item.clone()).collect(),
+            ..Default::default()
+        })
+    }
+
+    async fn size(&self, path: &ContentPath) -> Result<usize, Error> {
+        Ok(self.data(path)?.len())
+    }
+
+    async fn open(&self, path: &ContentPath, range: Range) -> Result<Content, Error> {
+        let data = self.data(path)?;
+        if range.last() >= data.len() {
+            return Err(Error::InvalidRange(format!("{range} beyond size {}", data.len())));
+        }
+
+        Ok(Content {
+            size: data.len(),
+            reader: Box::new(std::io::Cursor::new(data.slice(range.first()..=range.last()))),
+        })
+    }
+
+    async fn remove_item(&self, path: &ContentPath) -> Result<MetadataItem, Error> {
+        self.remove(path).ok_or_else(|| Error::NotFound(path.to_url_path()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content::provider_response;
+
+    async fn read(provider: &dyn ContentProvider, path: &ContentPath, range: Range) -> Vec<u8> {
+        let mut content = provider.open(path, range).await.unwrap();
+        let mut data = vec![];
+        content.reader.read_to_end(&mut data).await.unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn test_memory_provider() {
+        let provider = MemoryProvider::default();
+        let item = provider.insert("game.xvc", &b"0123456789"[..]);
+        let path = ContentPath::from_url_path(&item.path).unwrap();
+
+        assert_eq!(item.package_family_name, "game");
+        assert_eq!(provider.list().await.unwrap().items.len(), 1);
+        assert_eq!(provider.size(&path).await.unwrap(), 10);
+        assert_eq!(read(&provider, &path, Range::new(2, 4).unwrap()).await, b"234");
+        assert!(provider.open(&path, Range::new(5, 10).unwrap()).await.is_err());
+
+        provider.remove(&path).unwrap();
+        assert!(matches!(provider.size(&path).await, Err(Error::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_file_system_provider() {
+        let dir = std::env::temp_dir().join(format!("provider-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("app.appx"), b"abcdef").unwrap();
+
+        let drive_id = Uuid::new_v4();
+        let provider = FileSystemProvider::from_dir(&dir, drive_id).unwrap();
+        let listed = ContentPath::new(drive_id, "app.appx");
+        assert_eq!(provider.list().await.unwrap().items[0].path, listed.to_url_path());
+        assert_eq!(read(&provider, &listed, Range::new(1, 3).unwrap()).await, b"bcd");
+
+        // Files added later are found by name, nothing outside the root
+        std::fs::write(dir.join("late.appx"), b"xyz").unwrap();
+        let late = ContentPath::new(Uuid::new_v4(), "late.appx");
+        assert_eq!(provider.size(&late).await.unwrap(), 3);
+        for name in ["missing.appx", "..", "../etc/passwd"] {
+            let path = ContentPath::new(drive_id, name);
+            assert!(matches!(provider.size(&path).await, Err(Error::NotFound(_))), "{name}");
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_system_provider_packages() {
+        let dir = std::env::temp_dir().join(format!("provider-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("split.appx.001"), b"abc").unwrap();
+        std::fs::write(dir.join("split.appx.002"), b"def").unwrap();
+        let mut builder = tar::Builder::new(std::fs::File::create(dir.join("archive.tar")).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_cksum();
+        builder.append_data(&mut header, "packed.xvc", &b"12345"[..]).unwrap();
+        builder.into_inner().unwrap();
+
+        let drive_id = Uuid::new_v4();
+        let provider = FileSystemProvider::from_dir(&dir, drive_id).unwrap();
+        let names: Vec<_> = provider.list().await.unwrap().items.into_iter().map(|item| (item.package_family_name, item.size)).collect();
+        assert_eq!(names, [("packed".to_string(), 5), ("split".to_string(), 6)]);
+
+        let split = ContentPath::new(drive_id, "split.appx");
+        assert_eq!(provider.size(&split).await.unwrap(), 6);
+        assert_eq!(read(&provider, &split, Range::new(1, 4).unwrap()).await, b"bcde");
+        let packed = ContentPath::new(drive_id, "packed.xvc");
+        assert_eq!(read(&provider, &packed, Range::new(3, 4).unwrap()).await, b"45");
+
+        // Split files added later are found by name too
+        std::fs::write(dir.join("late.appx.001"), b"xy").unwrap();
+        std::fs::write(dir.join("late.appx.002"), b"z").unwrap();
+        let late = ContentPath::new(drive_id, "late.appx");
+        assert_eq!(read(&provider, &late, Range::new(0, 2).unwrap()).await, b"xyz");
+
+        // Served by name under any drive id, all resolving to the same item
+        let alias = ContentPath::new(Uuid::new_v4(), "late.appx");
+        assert_eq!(read(&provider, &alias, Range::new(0, 0).unwrap()).await, b"x");
+        assert_eq!(provider.canonical_path(&alias), late);
+        assert_eq!(provider.canonical_path(&split), split);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_system_provider_reload() {
+        let dir = std::env::temp_dir().join(format!("provider-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("kept.appx"), b"abc").unwrap();
+        std::fs::write(dir.join("hidden.appx"), b"def").unwrap();
+
+        let drive_id = Uuid::new_v4();
+        let provider = FileSystemProvider::from_dir(&dir, drive_id).unwrap();
+        let kept = ContentPath::new(drive_id, "kept.appx");
+        let hidden = ContentPath::new(drive_id, "hidden.appx");
+        provider.remove_item(&hidden).await.unwrap();
+        let etag = provider.etag(&kept).await.unwrap().unwrap();
+
+        // Changed on disk: the listed size is served until reloaded
+        std::fs::write(dir.join("kept.appx"), b"abcdef").unwrap();
+        std::fs::write(dir.join("new.appx"), b"ghi").unwrap();
+        assert_eq!(provider.size(&kept).await.unwrap(), 3);
+        assert!(provider.open(&kept, Range::new(0, 2).unwrap()).await.is_err());
+
+        let diff = provider.reload().unwrap();
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.changed.len(), 1);
+        assert!(diff.removed.is_empty());
+        assert_eq!(provider.size(&kept).await.unwrap(), 6);
+        assert_eq!(read(&provider, &kept, Range::new(3, 5).unwrap()).await, b"def");
+
+        // Ranges of the old version are answered with all of the new one
+        let current = provider.etag(&kept).await.unwrap().unwrap();
+        assert_ne!(current, etag);
+        let range = || Some("bytes=3-5".parse().unwrap());
+        let (response, served) = provider_response(&provider, &kept, range(), Some(&etag)).await.unwrap();
+        assert_eq!((response.status().as_u16(), served), (200, Range::new(0, 5).ok()));
+        assert_eq!(response.headers()["etag"], current.as_str());
+        let (response, served) = provider_response(&provider, &kept, range(), Some(&current)).await.unwrap();
+        assert_eq!((response.status().as_u16(), served), (206, Range::new(3, 5).ok()));
+
+        // Removed items stay removed
+        assert!(matches!(provider.size(&hidden).await, Err(Error::NotFound(_))));
+        assert_eq!(provider.list().await.unwrap().items.len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
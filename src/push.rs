@@ -0,0 +1,282 @@
+//! Push support: offering local packages to a console.
+//!
+//! Copy-on-LAN has no upload request, a push is a pull initiated by the
+//! console. The server lists the package in `/col/metadata`, announces
+//! itself and the console fetches the content in ranges. [`TransferTracker`]
+//! records which byte ranges have been served, so the push can be reported
+//! as complete once every byte of the package went out.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::body::Bytes;
+use http_body::Body;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::{error::Error, models::MetadataItem, vfs::FileStamp, ContentPath};
+
+/// Local file offered to consoles
+#[derive(Debug, Clone)]
+pub struct PushItem {
+    pub file: PathBuf,
+    pub content_path: ContentPath,
+    pub item: MetadataItem,
+    /// The file as described by `item`
+    pub stamp: FileStamp,
+}
+
+impl PushItem {
+    /// Describe a local package file
+    ///
+    /// The package family name is taken from the file stem, the remaining
+    /// metadata flags mirror what a console reports for a sideloaded app, see
+    /// [`sideload_metadata`].
+    pub fn from_file(file: impl AsRef<Path>, drive_id: Uuid) -> Result<Self, Error> {
+        let file = file.as_ref().to_path_buf();
+        let stamp = FileStamp::of(&std::fs::metadata(&file)?);
+        let size = stamp.len as usize;
+        let name = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or(Error::GeneralError(format!("Invalid file name: {file:?}")))?;
+        let family_name = file
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(name);
+
+        let content_path = ContentPath::new(drive_id, name);
+        let item = sideload_metadata(&content_path, family_name, size);
+
+        Ok(Self {
+            file,
+            content_path,
+            item,
+            stamp,
+        })
+    }
+}
+
+/// Metadata a console reports for a sideloaded app
+pub fn sideload_metadata(content_path: &ContentPath, family_name: &str, size: usize) -> MetadataItem {
+    MetadataItem {
+        typ: "app".into(),
+        has_content_id: false,
+        is_xvc: None,
+        content_id: String::new(),
+        product_id: String::new(),
+        package_family_name: family_name.into(),
+        one_store_product_id: String::new(),
+        version: "0".into(),
+        size,
+        allowed_product_id: String::new(),
+        allowed_package_family_name: String::new(),
+        path: content_path.to_url_path(),
+        availability: "available".into(),
+        generation: "uwpgen9".into(),
+        related_media: vec![],
+        related_media_family_names: vec![],
+        extra: Default::default(),
+    }
+}
+
+/// Byte ranges served per content path
+#[derive(Debug, Default)]
+pub struct TransferTracker {
+    served: Mutex<HashMap<String, Vec<(u64, u64)>>>,
+    notify: Notify,
+}
+
+impl TransferTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that the inclusive byte range `first..=last` of `path` was served
+    pub fn record(&self, path: &str, first: u64, last: u64) {
+        let mut served = self.served.lock().unwrap_or_else(|e| e.into_inner());
+        let ranges = served.entry(path.to_owned()).or_default();
+        ranges.push((first, last));
+        ranges.sort_unstable();
+
+        // Merge overlapping and adjacent ranges
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+        for &(first, last) in ranges.iter() {
+            match merged.last_mut() {
+                Some(prev) if first <= prev.1.saturating_add(1) => prev.1 = prev.1.max(last),
+                _ => merged.push((first, last)),
+            }
+        }
+        *ranges = merged;
+
+        self.notify.notify_waiters();
+    }
+
+    /// Number of distinct bytes of `path` served so far
+    pub fn served_bytes(&self, path: &str) -> u64 {
+        let served = self.served.lock().unwrap_or_else(|e| e.into_inner());
+        served
+            .get(path)
+            .map(|ranges| ranges.iter().map(|(first, last)| last - first + 1).sum())
+            .unwrap_or_default()
+    }
+
+    pub fn is_complete(&self, path: &str, size: u64) -> bool {
+        self.served_bytes(path) >= size
+    }
+
+    /// Wait until every byte of `path` was served, calling `progress` on each update
+    pub async fn wait_for(&self, path: &str, size: u64, timeout: Option<Duration>, mut progress: impl FnMut(u64)) -> Result<(), Error> {
+        let wait = async {
+            loop {
+                let notified = self.notify.notified();
+                let served = self.served_bytes(path);
+                progress(served);
+                if served >= size {
+                    break;
+                }
+                notified.await;
+            }
+        };
+
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, wait)
+                .await
+                .map_err(|_| Error::GeneralError(format!("Timed out waiting for console to pull {path}"))),
+            None => {
+                wait.await;
+                Ok(())
+            }
+        }
+    }
+
+    /// Wait until every one of `items` was served, within `timeout` in total
+    pub async fn wait_for_items(&self, items: &[PushItem], timeout: Option<Duration>) -> Result<(), Error> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        for push_item in items {
+            let item = &push_item.item;
+            log::info!("Offering {} ({} bytes), waiting for console to pull", item.package_family_name, item.size);
+
+            let mut reported = 0;
+            let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            self.wait_for(&item.path, item.size as u64, remaining, |served| {
+                if served > reported {
+                    log::info!("{}: {served}/{} bytes pulled", item.package_family_name, item.size);
+                    reported = served;
+                }
+            }).await?;
+
+            log::info!("Push of {} completed", item.package_family_name);
+        }
+
+        Ok(())
+    }
+}
+
+/// Response body recording its byte range with a [`TransferTracker`] once fully sent
+pub struct TrackedBody<B> {
+    inner: B,
+    tracker: Arc<TransferTracker>,
+    path: String,
+    range: Option<(u64, u64)>,
+    sent: u64,
+}
+
+impl<B> TrackedBody<B> {
+    pub fn new(inner: B, tracker: Arc<TransferTracker>, path: &str, first: u64, last: u64) -> Self {
+        Self {
+            inner,
+            tracker,
+            path: path.to_owned(),
+            range: Some((first, last)),
+            sent: 0,
+        }
+    }
+}
+
+impl<B> Body for TrackedBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let polled = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &polled {
+            self.sent += chunk.len() as u64;
+        }
+
+        // hyper stops polling once content-length bytes went out, so the end
+        // of stream is not necessarily observed
+        let complete = matches!(polled, Poll::Ready(None))
+            || matches!(self.range, Some((first, last)) if self.sent > last - first);
+        if complete {
+            if let Some((first, last)) = self.range.take() {
+                self.tracker.record(&self.path, first, last);
+            }
+        }
+
+        polled
+    }
+
+    fn poll_trailers(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<axum::http::HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker_coverage() {
+        let tracker = TransferTracker::new();
+        tracker.record("/a", 0, 0);
+        assert_eq!(tracker.served_bytes("/a"), 1);
+
+        tracker.record("/a", 0, 9);
+        tracker.record("/a", 20, 29);
+        assert_eq!(tracker.served_bytes("/a"), 20);
+        assert!(!tracker.is_complete("/a", 30));
+
+        tracker.record("/a", 10, 19);
+        assert!(tracker.is_complete("/a", 30));
+        assert_eq!(tracker.served_bytes("/b"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_completion() {
+        let tracker = std::sync::Arc::new(TransferTracker::new());
+        let waiter = tracker.clone();
+        let handle = tokio::spawn(async move {
+            waiter.wait_for("/a", 10, Some(Duration::from_secs(5)), |_| {}).await
+        });
+
+        tracker.record("/a", 0, 4);
+        tokio::task::yield_now().await;
+        tracker.record("/a", 5, 9);
+        handle.await.unwrap().expect("Push did not complete");
+
+        let body = TrackedBody::new(axum::body::Full::new(Bytes::from_static(b"abc")), tracker.clone(), "/c", 0, 2);
+        assert_eq!(tracker.served_bytes("/c"), 0);
+        hyper::body::to_bytes(body).await.unwrap();
+        assert_eq!(tracker.served_bytes("/c"), 3);
+
+        let result = tracker.wait_for("/b", 1, Some(Duration::from_millis(10)), |_| {}).await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,277 @@
+//! Persistent download queue.
+//!
+//! Jobs are stored as JSON and saved after every state change. Jobs that were
+//! active when the process died are picked up again as pending on
+//! [`TransferQueue::open`], and [`download_job`] resumes partial files.
+use std::{
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{chunking::ChunkSizing, error::Error, summary::TransferSummary, Client};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobState {
+    Pending,
+    Active,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Job {
+    pub id: u64,
+    pub console_id: String,
+    /// `MetadataItem::path` or package family name of the item to download
+    pub item_path: String,
+    pub destination: PathBuf,
+    pub state: JobState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QueueFile {
+    next_id: u64,
+    jobs: Vec<Job>,
+}
+
+pub struct TransferQueue {
+    path: PathBuf,
+    state: QueueFile,
+}
+
+impl TransferQueue {
+    /// Open the queue stored at `path`, starting empty if it does not exist yet
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut state: QueueFile = match std::fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => QueueFile::default(),
+            Err(e) => Err(e)?,
+        };
+
+        // Jobs active during a crash are resumed
+        for job in state.jobs.iter_mut().filter(|job| job.state == JobState::Active) {
+            log::info!("Resuming interrupted job {}: {}", job.id, job.item_path);
+            job.state = JobState::Pending;
+        }
+
+        Ok(Self { path, state })
+    }
+
+    pub fn jobs(&self) -> &[Job] {
+        &self.state.jobs
+    }
+
+    pub fn add(&mut self, console_id: &str, item_path: &str, destination: impl AsRef<Path>) -> Result<u64, Error> {
+        let id = self.state.next_id;
+        self.state.next_id += 1;
+        self.state.jobs.push(Job {
+            id,
+            console_id: console_id.to_owned(),
+            item_path: item_path.to_owned(),
+            destination: destination.as_ref().to_path_buf(),
+            state: JobState::Pending,
+            error: None,
+        });
+        self.save()?;
+
+        Ok(id)
+    }
+
+    /// Write the queue to disk, replacing the previous file atomically
+    pub fn save(&self) -> Result<(), Error> {
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&self.state)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+
+        Ok(())
+    }
+
+    fn take_pending(&mut self) -> Result<Option<Job>, Error> {
+        let Some(job) = self.state.jobs.iter_mut().find(|job| job.state == JobState::Pending) else {
+            return Ok(None);
+        };
+        job.state = JobState::Active;
+        job.error = None;
+        let job = job.clone();
+        self.save()?;
+
+        Ok(Some(job))
+    }
+
+    fn finish(&mut self, id: u64, result: Result<(), Error>) -> Result<(), Error> {
+        if let Some(job) = self.state.jobs.iter_mut().find(|job| job.id == id) {
+            match result {
+                Ok(()) => job.state = JobState::Completed,
+                // Picked up again by the next run, resuming the partial file
+                Err(Error::Cancelled) => job.state = JobState::Pending,
+                Err(e) => {
+                    log::error!("Job {id} failed: {e:?}");
+                    job.state = JobState::Failed;
+                    job.error = Some(format!("{e:?}"));
+                }
+            }
+        }
+
+        self.save()
+    }
+
+    /// Process pending jobs with up to `concurrency` workers
+    ///
+    /// `worker` performs a single job, e.g. via [`download_job`]. Returns once
+    /// no pending jobs are left, or once a job was cancelled.
+    pub fn run<F>(&mut self, concurrency: usize, worker: F) -> Result<(), Error>
+    where
+        F: Fn(&Job) -> Result<(), Error> + Sync,
+    {
+        let queue = Mutex::new(self);
+        let lock = || queue.lock().map_err(|_| Error::GeneralError("Queue lock poisoned".into()));
+
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..concurrency.max(1))
+                .map(|_| {
+                    scope.spawn(|| -> Result<(), Error> {
+                        loop {
+                            // Release the lock before running the job
+                            let next = lock()?.take_pending()?;
+                            let Some(job) = next else {
+                                return Ok(());
+                            };
+
+                            log::info!("Starting job {}: {} -> {:?}", job.id, job.item_path, job.destination);
+                            let result = worker(&job);
+                            let cancelled = matches!(result, Err(Error::Cancelled));
+                            lock()?.finish(job.id, result)?;
+                            if cancelled {
+                                log::info!("Job {} cancelled, left pending", job.id);
+                                return Ok(());
+                            }
+                        }
+                    })
+                })
+                .collect();
+
+            handles.into_iter().try_for_each(|handle| {
+                handle
+                    .join()
+                    .map_err(|_| Error::GeneralError("Queue worker panicked".into()))?
+            })
+        })
+    }
+}
+
+/// Download a job's item with `client`, resuming a partial destination file
+pub fn download_job(client: &Client, job: &Job, sizing: ChunkSizing) -> Result<TransferSummary, Error> {
+    let metadata = client.get_metadata()?;
+    let item = metadata
+        .items
+        .iter()
+        .find(|item| item.path == job.item_path || item.package_family_name == job.item_path)
+        .ok_or(Error::GeneralError(format!("Item not offered by console: {}", job.item_path)))?;
+
+    let content_length = client.get_item_filesize(item)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&job.destination)?;
+
+    let offset = file.metadata()?.len() as usize;
+    if offset > content_length {
+        return Err(Error::GeneralError(format!(
+            "Destination {:?} larger than item ({offset} > {content_length})",
+            job.destination
+        )));
+    }
+    if offset > 0 {
+        log::info!("Resuming {:?} at {offset}/{content_length}", job.destination);
+    }
+
+    client.download_chunks_adaptive(item, offset, content_length, &mut file, sizing, |progress| {
+        log::debug!(
+            "Job {}: {}/{} bytes, next chunk {} bytes",
+            job.id, progress.written, progress.total, progress.next_chunk_size
+        );
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{name}.json", std::process::id()))
+    }
+
+    #[test]
+    fn test_queue_persistence() {
+        let path = queue_path("persist");
+        let mut queue = TransferQueue::open(&path).unwrap();
+        assert_eq!(queue.add("X1", "/col/content/a", "a.bin").unwrap(), 0);
+        assert_eq!(queue.add("X1", "/col/content/b", "b.bin").unwrap(), 1);
+
+        // Simulate a crash while the first job is active
+        queue.take_pending().unwrap().unwrap();
+        let queue = TransferQueue::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(queue.jobs().len(), 2);
+        assert!(queue.jobs().iter().all(|job| job.state == JobState::Pending));
+        assert_eq!(queue.jobs()[1].destination, PathBuf::from("b.bin"));
+    }
+
+    #[test]
+    fn test_queue_run() {
+        let path = queue_path("run");
+        let mut queue = TransferQueue::open(&path).unwrap();
+        for idx in 0..5 {
+            queue.add("X1", &format!("/col/content/{idx}"), format!("{idx}.bin")).unwrap();
+        }
+
+        queue.run(3, |job| match job.id {
+            3 => Err(Error::GeneralError("boom".into())),
+            _ => Ok(()),
+        }).unwrap();
+
+        let queue = TransferQueue::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let states: Vec<JobState> = queue.jobs().iter().map(|job| job.state).collect();
+        assert_eq!(states, vec![
+            JobState::Completed,
+            JobState::Completed,
+            JobState::Completed,
+            JobState::Failed,
+            JobState::Completed,
+        ]);
+        assert!(queue.jobs()[3].error.as_deref().unwrap().contains("boom"));
+    }
+
+    #[test]
+    fn test_queue_run_cancelled() {
+        let path = queue_path("cancel");
+        let mut queue = TransferQueue::open(&path).unwrap();
+        for idx in 0..3 {
+            queue.add("X1", &format!("/col/content/{idx}"), format!("{idx}.bin")).unwrap();
+        }
+
+        queue.run(1, |job| match job.id {
+            1 => Err(Error::Cancelled),
+            _ => Ok(()),
+        }).unwrap();
+
+        let queue = TransferQueue::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let states: Vec<JobState> = queue.jobs().iter().map(|job| job.state).collect();
+        assert_eq!(states, vec![JobState::Completed, JobState::Pending, JobState::Pending]);
+        assert!(queue.jobs()[1].error.is_none());
+    }
+}
@@ -0,0 +1,342 @@
+//! Byte ranges and their HTTP header forms.
+//!
+//! - [`Range`]: validated inclusive range, `range: bytes=a-b`
+//! - [`RangeRequest`]: any single-range `range` header, including open-ended
+//!   (`bytes=a-`) and suffix (`bytes=-n`) ranges
+//! - [`ContentRange`]: `content-range: bytes a-b/total` of a response
+use std::{fmt, str::FromStr};
+
+use crate::error::Error;
+
+/// Inclusive byte range `first..=last`, never empty
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Range {
+    first_byte: usize,
+    last_byte: usize,
+}
+
+impl Range {
+    pub fn new(first: usize, last: usize) -> Result<Self, Error> {
+        if last < first {
+            return Err(Error::InvalidRange(format!("{first}-{last}: last byte before first byte")));
+        }
+
+        Ok(Self {
+            first_byte: first,
+            last_byte: last,
+        })
+    }
+
+    /// Range of `len` bytes starting at `first`
+    pub fn from_len(first: usize, len: usize) -> Result<Self, Error> {
+        let last = len
+            .checked_sub(1)
+            .and_then(|len| first.checked_add(len))
+            .ok_or(Error::InvalidRange(format!("{len} bytes at {first}")))?;
+
+        Self::new(first, last)
+    }
+
+    /// Single byte at `offset`, e.g. to probe the total size
+    pub const fn single(offset: usize) -> Self {
+        Self {
+            first_byte: offset,
+            last_byte: offset,
+        }
+    }
+
+    pub fn first(&self) -> usize {
+        self.first_byte
+    }
+
+    pub fn last(&self) -> usize {
+        self.last_byte
+    }
+
+    pub fn count(&self) -> usize {
+        self.last_byte - self.first_byte + 1
+    }
+
+    /// Same range moved `by` bytes further
+    pub fn offset(&self, by: usize) -> Result<Self, Error> {
+        match (self.first_byte.checked_add(by), self.last_byte.checked_add(by)) {
+            (Some(first), Some(last)) => Self::new(first, last),
+            _ => Err(Error::InvalidRange(format!("{self} offset by {by}"))),
+        }
+    }
+}
+
+/// `bytes=a-b`, the `range` request header value
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bytes={}-{}", self.first_byte, self.last_byte)
+    }
+}
+
+impl FromStr for Range {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse()? {
+            RangeRequest::Bounded(range) => Ok(range),
+            other => Err(Error::InvalidRange(format!("{other}: not a bounded range"))),
+        }
+    }
+}
+
+/// Single range as requested via the `range` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RangeRequest {
+    /// `bytes=a-b`
+    Bounded(Range),
+    /// `bytes=a-`, from `a` to the end
+    From(usize),
+    /// `bytes=-n`, the last `n` bytes
+    Suffix(usize),
+}
+
+impl RangeRequest {
+    /// Concrete range within a resource of `total` bytes, `None` if unsatisfiable
+    ///
+    /// Bounded ranges reaching past the end are clamped, as per RFC 9110.
+    pub fn resolve(&self, total: usize) -> Option<Range> {
+        let last = total.checked_sub(1)?;
+        match *self {
+            Self::Bounded(range) if range.first() <= last => Range::new(range.first(), range.last().min(last)).ok(),
+            Self::From(first) if first <= last => Range::new(first, last).ok(),
+            Self::Suffix(len) if len > 0 => Range::new(total.saturating_sub(len), last).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl From<Range> for RangeRequest {
+    fn from(range: Range) -> Self {
+        Self::Bounded(range)
+    }
+}
+
+impl fmt::Display for RangeRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bounded(range) => range.fmt(f),
+            Self::From(first) => write!(f, "bytes={first}-"),
+            Self::Suffix(len) => write!(f, "bytes=-{len}"),
+        }
+    }
+}
+
+fn parse_position(s: &str, header: &str) -> Result<usize, Error> {
+    // `usize::from_str` accepts a leading `+`, HTTP does not
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::InvalidRange(format!("{header}: invalid position {s:?}")));
+    }
+
+    s.parse()
+        .map_err(|_| Error::InvalidRange(format!("{header}: position out of range")))
+}
+
+impl FromStr for RangeRequest {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let spec = s
+            .trim()
+            .strip_prefix("bytes=")
+            .ok_or(Error::InvalidRange(format!("{s}: unit is not bytes")))?;
+        if spec.contains(',') {
+            return Err(Error::InvalidRange(format!("{s}: multiple ranges are not supported")));
+        }
+
+        let (first, last) = spec
+            .trim()
+            .split_once('-')
+            .ok_or(Error::InvalidRange(format!("{s}: missing '-'")))?;
+
+        match (first.trim(), last.trim()) {
+            ("", len) => Ok(Self::Suffix(parse_position(len, s)?)),
+            (first, "") => Ok(Self::From(parse_position(first, s)?)),
+            (first, last) => Ok(Self::Bounded(Range::new(parse_position(first, s)?, parse_position(last, s)?)?)),
+        }
+    }
+}
+
+/// `content-range` response header value
+///
+/// `range` is `None` for the unsatisfied form `bytes */total`, `total` is
+/// `None` if the server does not know the size (`bytes a-b/*`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContentRange {
+    pub range: Option<Range>,
+    pub total: Option<usize>,
+}
+
+impl ContentRange {
+    pub fn new(range: Range, total: usize) -> Result<Self, Error> {
+        if range.last() >= total {
+            return Err(Error::InvalidRange(format!("{range} beyond total size {total}")));
+        }
+
+        Ok(Self {
+            range: Some(range),
+            total: Some(total),
+        })
+    }
+
+    /// `bytes */total`, sent along with `416 Range Not Satisfiable`
+    pub fn unsatisfied(total: usize) -> Self {
+        Self {
+            range: None,
+            total: Some(total),
+        }
+    }
+}
+
+impl fmt::Display for ContentRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.range {
+            Some(range) => write!(f, "bytes {}-{}/", range.first(), range.last())?,
+            None => write!(f, "bytes */")?,
+        }
+        match self.total {
+            Some(total) => write!(f, "{total}"),
+            None => write!(f, "*"),
+        }
+    }
+}
+
+impl FromStr for ContentRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let spec = s
+            .trim()
+            .strip_prefix("bytes ")
+            .ok_or(Error::InvalidRange(format!("{s}: unit is not bytes")))?;
+        let (range, total) = spec
+            .split_once('/')
+            .ok_or(Error::InvalidRange(format!("{s}: missing total size")))?;
+
+        let total = match total.trim() {
+            "*" => None,
+            total => Some(parse_position(total, s)?),
+        };
+        let range = match range.trim() {
+            "*" => None,
+            range => {
+                let (first, last) = range
+                    .split_once('-')
+                    .ok_or(Error::InvalidRange(format!("{s}: missing '-'")))?;
+                Some(Range::new(parse_position(first, s)?, parse_position(last, s)?)?)
+            }
+        };
+
+        match (range, total) {
+            (None, None) => Err(Error::InvalidRange(format!("{s}: neither range nor total size"))),
+            (Some(range), Some(total)) if range.last() >= total => {
+                Err(Error::InvalidRange(format!("{s}: range beyond total size")))
+            }
+            (range, total) => Ok(Self { range, total }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_range_validation() {
+        assert!(Range::new(10, 9).is_err());
+        assert_eq!(Range::new(5, 5).unwrap().count(), 1);
+        assert_eq!(Range::from_len(1024, 1024).unwrap(), Range::new(1024, 2047).unwrap());
+        assert!(Range::from_len(0, 0).is_err());
+        assert!(Range::single(usize::MAX).offset(1).is_err());
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        assert_eq!("bytes=0-0".parse::<Range>().unwrap(), Range::single(0));
+        assert_eq!("bytes=100-".parse::<RangeRequest>().unwrap(), RangeRequest::From(100));
+        assert_eq!("bytes=-500".parse::<RangeRequest>().unwrap(), RangeRequest::Suffix(500));
+        assert!("bytes=100-".parse::<Range>().is_err());
+        assert!("bytes=5-4".parse::<RangeRequest>().is_err());
+        assert!("bytes=0-1,4-5".parse::<RangeRequest>().is_err());
+        assert!("items=0-1".parse::<RangeRequest>().is_err());
+        assert!("bytes=+1-2".parse::<RangeRequest>().is_err());
+
+        let content_range: ContentRange = "bytes 0-0/105205760".parse().unwrap();
+        assert_eq!(content_range.range, Some(Range::single(0)));
+        assert_eq!(content_range.total, Some(105205760));
+        assert_eq!("bytes */1000".parse::<ContentRange>().unwrap(), ContentRange::unsatisfied(1000));
+        assert_eq!("bytes 0-9/*".parse::<ContentRange>().unwrap().total, None);
+        assert!("bytes 0-10/10".parse::<ContentRange>().is_err());
+        assert!("bytes */*".parse::<ContentRange>().is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let range = |first, last| Some(Range::new(first, last).unwrap());
+        assert_eq!(RangeRequest::Bounded(Range::new(0, 99).unwrap()).resolve(50), range(0, 49));
+        assert_eq!(RangeRequest::Bounded(Range::new(50, 99).unwrap()).resolve(50), None);
+        assert_eq!(RangeRequest::From(10).resolve(50), range(10, 49));
+        assert_eq!(RangeRequest::Suffix(10).resolve(50), range(40, 49));
+        assert_eq!(RangeRequest::Suffix(100).resolve(50), range(0, 49));
+        assert_eq!(RangeRequest::Suffix(0).resolve(50), None);
+        assert_eq!(RangeRequest::From(0).resolve(0), None);
+    }
+
+    fn range_strategy() -> impl Strategy<Value = Range> {
+        (0..usize::MAX / 2, 0..usize::MAX / 2).prop_map(|(first, len)| Range::new(first, first + len).unwrap())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_range_roundtrip(range in range_strategy()) {
+            prop_assert_eq!(range.to_string().parse::<Range>().unwrap(), range);
+            prop_assert_eq!(range.count(), range.last() - range.first() + 1);
+        }
+
+        #[test]
+        fn prop_range_request_roundtrip(first in any::<usize>(), len in any::<usize>()) {
+            for request in [RangeRequest::From(first), RangeRequest::Suffix(len)] {
+                prop_assert_eq!(request.to_string().parse::<RangeRequest>().unwrap(), request);
+            }
+        }
+
+        #[test]
+        fn prop_new_rejects_inverted(first in 1..usize::MAX, delta in 1..usize::MAX) {
+            prop_assert!(Range::new(first, first.saturating_sub(delta)).is_err());
+        }
+
+        #[test]
+        fn prop_content_range_roundtrip(range in range_strategy(), extra in 1..1024usize) {
+            let content_range = ContentRange::new(range, range.last() + extra).unwrap();
+            prop_assert_eq!(content_range.to_string().parse::<ContentRange>().unwrap(), content_range);
+        }
+
+        #[test]
+        fn prop_resolve_within_bounds(request in prop_oneof![
+            range_strategy().prop_map(RangeRequest::Bounded),
+            (0..4096usize).prop_map(RangeRequest::From),
+            (0..4096usize).prop_map(RangeRequest::Suffix),
+        ], total in 0..4096usize) {
+            if let Some(range) = request.resolve(total) {
+                prop_assert!(range.last() < total);
+            }
+        }
+
+        #[test]
+        fn prop_iterate_range_covers(size in 1..100_000usize, step in 1..10_000usize) {
+            let mut expected = 0;
+            for range in crate::Client::iterate_range(size, step) {
+                prop_assert_eq!(range.first(), expected);
+                prop_assert!(range.count() <= step);
+                expected = range.last() + 1;
+            }
+            prop_assert_eq!(expected, size);
+        }
+    }
+}
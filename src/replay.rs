@@ -0,0 +1,187 @@
+//! Replay of recorded proxy captures, used by the `replay` binary.
+//!
+//! [`router`] answers every request with the response recorded for it in a
+//! [`ReplayIndex`], so a client can be run against a capture instead of a
+//! console.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::{Body, Bytes, Full},
+    extract::State,
+    http::{HeaderName, HeaderValue, Request, Response, StatusCode},
+    Router,
+};
+
+use crate::{
+    capture::{CapturedResponse, Exchange},
+    error::Error,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ReplayKey {
+    method: String,
+    path: String,
+    range: Option<String>,
+}
+
+/// Recorded responses, keyed by method, path and `range` header
+///
+/// If the same request was recorded several times, the responses are
+/// handed out in recording order, repeating the last one when exhausted.
+#[derive(Debug, Default)]
+pub struct ReplayIndex {
+    responses: HashMap<ReplayKey, Vec<CapturedResponse>>,
+    served: Mutex<HashMap<ReplayKey, usize>>,
+}
+
+impl ReplayIndex {
+    pub fn new(exchanges: Vec<Exchange>) -> Self {
+        let mut responses: HashMap<ReplayKey, Vec<CapturedResponse>> = HashMap::new();
+        for exchange in exchanges {
+            let key = ReplayKey {
+                range: exchange.request.header("range").map(str::to_owned),
+                method: exchange.request.method,
+                path: exchange.request.path,
+            };
+            responses.entry(key).or_default().push(exchange.response);
+        }
+
+        Self {
+            responses,
+            served: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.responses.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.responses.is_empty()
+    }
+
+    pub fn find(&self, method: &str, path: &str, range: Option<&str>) -> Option<&CapturedResponse> {
+        let key = ReplayKey {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            range: range.map(str::to_owned),
+        };
+        let responses = self.responses.get(&key)?;
+
+        let mut served = self.served.lock().unwrap_or_else(|e| e.into_inner());
+        let count = served.entry(key).or_default();
+        let response = responses.get(*count).or(responses.last());
+        *count += 1;
+
+        response
+    }
+}
+
+/// Router answering with the recorded responses of `index`, `404` for unrecorded requests
+pub fn router(index: ReplayIndex) -> Router {
+    Router::new()
+        .fallback(replay_handler)
+        .with_state(Arc::new(index))
+}
+
+async fn replay_handler(State(index): State<Arc<ReplayIndex>>, request: Request<Body>) -> Response<Full<Bytes>> {
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+    let range = request
+        .headers()
+        .get("range")
+        .and_then(|value| value.to_str().ok());
+
+    let Some(recorded) = index.find(request.method().as_str(), path, range) else {
+        log::warn!("No recorded response for {} {path} (range: {range:?})", request.method());
+        let mut response = Response::new(Full::from("No recorded response"));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    };
+    log::info!("{} {path} (range: {range:?}) -> {} {}", request.method(), recorded.status, recorded.reason);
+
+    match build_response(recorded) {
+        Ok(response) => response,
+        Err(e) => {
+            log::error!("Failed replaying {path}: {e:?}");
+            let mut response = Response::new(Full::from("Invalid recorded response"));
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    }
+}
+
+fn build_response(recorded: &CapturedResponse) -> Result<Response<Full<Bytes>>, Error> {
+    let invalid = |e: &dyn std::fmt::Display| Error::GeneralError(format!("Invalid recorded response: {e}"));
+
+    let body = recorded.body.bytes()?;
+    let captured = body.len();
+    let mut response = Response::new(Full::from(body));
+    *response.status_mut() = StatusCode::from_u16(recorded.status).map_err(|e| invalid(&e))?;
+
+    for header in &recorded.headers {
+        // Bodies are stored decoded, so framing is up to hyper
+        if header.name.eq_ignore_ascii_case("transfer-encoding") {
+            continue;
+        }
+        if recorded.body.truncated && header.name.eq_ignore_ascii_case("content-length") {
+            log::warn!("Replaying truncated body ({} of {} bytes)", captured, recorded.body.size);
+            continue;
+        }
+
+        response.headers_mut().append(
+            HeaderName::from_bytes(header.name.as_bytes()).map_err(|e| invalid(&e))?,
+            HeaderValue::from_str(&header.value).map_err(|e| invalid(&e))?,
+        );
+    }
+
+    if Some(recorded.reason.as_str()) != response.status().canonical_reason() {
+        let reason = hyper::ext::ReasonPhrase::try_from(recorded.reason.clone()).map_err(|e| invalid(&e))?;
+        response.extensions_mut().insert(reason);
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use crate::capture::{CapturedRequest, DEFAULT_MAX_BODY};
+
+    fn exchange(request: &[u8], response: &[u8]) -> Exchange {
+        Exchange::new(
+            None,
+            UNIX_EPOCH,
+            Duration::ZERO,
+            CapturedRequest::parse(request, DEFAULT_MAX_BODY).unwrap(),
+            CapturedResponse::parse(response, DEFAULT_MAX_BODY).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_replay_lookup() {
+        let index = ReplayIndex::new(vec![
+            exchange(b"GET /col/metadata HTTP/1.1\r\n\r\n", b"HTTP/1.1 200 OK\r\nContent-Type: text/json\r\nContent-Length: 1\r\n\r\na"),
+            exchange(b"GET /col/metadata HTTP/1.1\r\n\r\n", b"HTTP/1.1 200 OK\r\nContent-Type: text/json\r\nContent-Length: 1\r\n\r\nb"),
+            exchange(b"GET /col/content/x HTTP/1.1\r\nRange: bytes=0-0\r\n\r\n", b"HTTP/1.1 206 OK\r\nContent-Length: 0\r\n\r\n"),
+        ]);
+        assert_eq!(index.len(), 3);
+
+        let body = |resp: Option<&CapturedResponse>| resp.unwrap().body.text.clone().unwrap();
+        assert_eq!(body(index.find("GET", "/col/metadata", None)), "a");
+        assert_eq!(body(index.find("GET", "/col/metadata", None)), "b");
+        assert_eq!(body(index.find("GET", "/col/metadata", None)), "b");
+
+        assert_eq!(index.find("GET", "/col/content/x", Some("bytes=0-0")).unwrap().status, 206);
+        assert!(index.find("GET", "/col/content/x", Some("bytes=1-1")).is_none());
+        assert!(index.find("HEAD", "/col/metadata", None).is_none());
+    }
+}
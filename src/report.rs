@@ -0,0 +1,204 @@
+//! Inventory reports of console metadata.
+//!
+//! [`InventoryRow`] pairs a [`MetadataItem`] with the console it was listed
+//! by, [`write_inventory`] renders rows as JSON, CSV or an aligned table.
+use std::{io::Write, str::FromStr};
+
+use indicatif::HumanBytes;
+use serde::Serialize;
+
+use crate::{error::Error, models::MetadataItem, Console};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Json,
+    Csv,
+    Table,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "table" => Ok(Self::Table),
+            _ => Err(Error::GeneralError(format!("Unknown output format: {s}"))),
+        }
+    }
+}
+
+/// Item listed by a console
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InventoryRow {
+    pub console_name: String,
+    pub console_id: String,
+    pub size_human: String,
+    #[serde(flatten)]
+    pub item: MetadataItem,
+}
+
+impl InventoryRow {
+    pub fn new(console: &Console, item: MetadataItem) -> Self {
+        Self {
+            console_name: console.name.clone(),
+            console_id: console.id.clone(),
+            size_human: HumanBytes(item.size as u64).to_string(),
+            item,
+        }
+    }
+
+    /// Every field as text, in [`CSV_HEADER`] order
+    fn fields(&self) -> [String; 19] {
+        let item = &self.item;
+        [
+            self.console_name.clone(),
+            self.console_id.clone(),
+            item.typ.clone(),
+            item.has_content_id.to_string(),
+            item.is_xvc.map(|xvc| xvc.to_string()).unwrap_or_default(),
+            item.content_id.clone(),
+            item.product_id.clone(),
+            item.package_family_name.clone(),
+            item.one_store_product_id.clone(),
+            item.version.clone(),
+            item.size.to_string(),
+            self.size_human.clone(),
+            item.allowed_product_id.clone(),
+            item.allowed_package_family_name.clone(),
+            item.path.clone(),
+            item.availability.clone(),
+            item.generation.clone(),
+            item.related_media.join(";"),
+            item.related_media_family_names.join(";"),
+        ]
+    }
+}
+
+pub const CSV_HEADER: [&str; 19] = [
+    "consoleName",
+    "consoleId",
+    "type",
+    "hasContentId",
+    "isXvc",
+    "contentId",
+    "productId",
+    "packageFamilyName",
+    "oneStoreProductId",
+    "version",
+    "size",
+    "sizeHuman",
+    "allowedProductId",
+    "allowedPackageFamilyName",
+    "path",
+    "availability",
+    "generation",
+    "relatedMedia",
+    "relatedMediaFamilyNames",
+];
+
+/// Quote a CSV field if it contains separators, quotes or line breaks
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_owned(),
+    }
+}
+
+pub fn write_inventory(writer: &mut impl Write, format: OutputFormat, rows: &[InventoryRow]) -> Result<(), Error> {
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *writer, rows)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::Csv => {
+            writeln!(writer, "{}", CSV_HEADER.join(","))?;
+            for row in rows {
+                let fields: Vec<String> = row.fields().iter().map(|field| csv_field(field)).collect();
+                writeln!(writer, "{}", fields.join(","))?;
+            }
+        }
+        OutputFormat::Table => {
+            let header = ["CONSOLE", "ID", "TYPE", "PACKAGE", "VERSION", "SIZE"];
+            let table: Vec<[&str; 6]> = rows
+                .iter()
+                .map(|row| [
+                    row.console_name.as_str(),
+                    row.console_id.as_str(),
+                    row.item.typ.as_str(),
+                    row.item.package_family_name.as_str(),
+                    row.item.version.as_str(),
+                    row.size_human.as_str(),
+                ])
+                .collect();
+
+            let mut widths = header.map(str::len);
+            for cells in &table {
+                for (width, cell) in widths.iter_mut().zip(cells) {
+                    *width = (*width).max(cell.len());
+                }
+            }
+
+            for cells in std::iter::once(&header).chain(&table) {
+                let line: Vec<String> = cells
+                    .iter()
+                    .zip(widths)
+                    .enumerate()
+                    .map(|(idx, (cell, width))| match idx {
+                        // Right-align the size column
+                        5 => format!("{cell:>width$}"),
+                        _ => format!("{cell:<width$}"),
+                    })
+                    .collect();
+                writeln!(writer, "{}", line.join("  ").trim_end())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_write_inventory() {
+        let console = Console {
+            address: Ipv4Addr::LOCALHOST,
+            port: 10248,
+            id: "X1".into(),
+            name: "XBOX".into(),
+        };
+        let rows = vec![InventoryRow::new(&console, MetadataItem {
+            typ: "app".into(),
+            package_family_name: "Some, Package".into(),
+            version: "1".into(),
+            size: 2048,
+            ..Default::default()
+        })];
+
+        let mut csv = vec![];
+        write_inventory(&mut csv, OutputFormat::Csv, &rows).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 2);
+        assert!(csv.lines().nth(1).unwrap().starts_with("XBOX,X1,app,false,,,,\"Some, Package\",,1,2048,2.00 KiB,"));
+
+        let mut json = vec![];
+        write_inventory(&mut json, OutputFormat::Json, &rows).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json[0]["consoleId"], "X1");
+        assert_eq!(json[0]["packageFamilyName"], "Some, Package");
+        assert_eq!(json[0]["sizeHuman"], "2.00 KiB");
+
+        let mut table = vec![];
+        write_inventory(&mut table, OutputFormat::Table, &rows).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        assert!(table.starts_with("CONSOLE  ID  TYPE  PACKAGE        VERSION      SIZE\n"));
+        assert!(table.ends_with("XBOX     X1  app   Some, Package  1        2.00 KiB\n"));
+    }
+}
@@ -0,0 +1,818 @@
+//! Console emulation: the HTTP side of a network-transfer server.
+//!
+//! [`router`] answers `/col/metadata` and `/col/content/...` the way a
+//! console does, serving the [`ContentProvider`] of its [`AppState`]. [`Server`]
+//! binds it and announces it via mDNS for as long as it runs.
+use std::{
+    collections::HashMap,
+    io::BufRead,
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use axum::{
+    body::{boxed, Body},
+    extract::{ConnectInfo, Json, Path, State},
+    http::{header::{self, HeaderMap}, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use network_interface::{NetworkInterface, NetworkInterfaceConfig};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    access::AccessPolicy,
+    admin,
+    content::{provider_response, SERVER},
+    error::Error,
+    generate_random_console_id,
+    metrics::{AccessEntry, LoggedBody, Metrics, ServedItem},
+    models::ContractVersionError,
+    provider::{ContentProvider, FileSystemProvider, MemoryProvider},
+    push::{PushItem, TrackedBody, TransferTracker},
+    range::RangeRequest,
+    throttle::{RateLimiter, ThrottledBody},
+    Announcement, Console, ContentPath, ContractVersion, NetworkTransferProtocol, SERVER_PORT,
+};
+
+/// State shared by the request handlers
+pub struct AppState {
+    provider: Arc<dyn ContentProvider>,
+    tracker: Arc<TransferTracker>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    client_rate_limit: Option<u64>,
+    client_rate_limiters: Mutex<HashMap<IpAddr, Arc<RateLimiter>>>,
+    access: AccessPolicy,
+    metrics: Arc<Metrics>,
+    console: RwLock<Option<Console>>,
+    announcement: Mutex<Option<Announcement>>,
+}
+
+impl AppState {
+    /// Serve content of `provider`, optionally rate limited in total and per client address
+    pub fn new(provider: Arc<dyn ContentProvider>, rate_limit: Option<u64>, client_rate_limit: Option<u64>) -> Self {
+        Self {
+            provider,
+            tracker: Arc::new(TransferTracker::new()),
+            rate_limiter: rate_limit.map(|rate| Arc::new(RateLimiter::new(rate))),
+            client_rate_limit,
+            client_rate_limiters: Mutex::new(HashMap::new()),
+            access: AccessPolicy::default(),
+            metrics: Arc::new(Metrics::new()),
+            console: RwLock::new(None),
+            announcement: Mutex::new(None),
+        }
+    }
+
+    /// Identity announced while serving
+    pub fn with_console(self, console: Console) -> Self {
+        *self.console.write().unwrap_or_else(|e| e.into_inner()) = Some(console);
+        self
+    }
+
+    pub fn console(&self) -> Option<Console> {
+        self.console.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Change the advertised console name, re-announcing if announced
+    ///
+    /// The new name is registered before the old service is withdrawn, which
+    /// blocks for up to a second. If registering fails, the old name stays
+    /// announced and in place.
+    pub fn rename(&self, name: &str) -> Result<Console, Error> {
+        // Held throughout, so concurrent renames cannot interleave
+        let mut announcement = self.announcement.lock().unwrap_or_else(|e| e.into_inner());
+        let mut console = self.console().ok_or(Error::GeneralError("Server has no console identity".into()))?;
+        console.name = name.to_owned();
+
+        if announcement.is_some() {
+            let renamed = NetworkTransferProtocol {}.register(&console)?;
+            if let Some(previous) = announcement.replace(renamed) {
+                if let Err(e) = previous.withdraw() {
+                    log::warn!("Failed withdrawing the previous name: {e:?}");
+                }
+            }
+        }
+        *self.console.write().unwrap_or_else(|e| e.into_inner()) = Some(console.clone());
+        log::info!("Console renamed to {name}");
+
+        Ok(console)
+    }
+
+    /// Announce the console via mDNS until [`AppState::withdraw`]
+    fn announce(&self) -> Result<(), Error> {
+        let console = self.console().ok_or(Error::GeneralError("Server has no console identity".into()))?;
+        let mut announcement = self.announcement.lock().unwrap_or_else(|e| e.into_inner());
+        *announcement = Some(NetworkTransferProtocol {}.register(&console)?);
+        Ok(())
+    }
+
+    fn withdraw(&self) -> Result<(), Error> {
+        let announcement = self.announcement.lock().unwrap_or_else(|e| e.into_inner()).take();
+        match announcement {
+            Some(announcement) => announcement.withdraw(),
+            None => Ok(()),
+        }
+    }
+
+    /// Admit only clients passing `access`, everyone is admitted by default
+    pub fn with_access_policy(mut self, access: AccessPolicy) -> Self {
+        self.access = access;
+        self
+    }
+
+    pub fn access_policy(&self) -> &AccessPolicy {
+        &self.access
+    }
+
+    /// Request and transfer counters, exposed on the admin port
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    pub fn provider(&self) -> &Arc<dyn ContentProvider> {
+        &self.provider
+    }
+
+    /// Tracks which ranges of each item path were served
+    pub fn tracker(&self) -> &Arc<TransferTracker> {
+        &self.tracker
+    }
+
+    /// Limiters applying to content served to `client`
+    fn rate_limiters(&self, client: IpAddr) -> Vec<Arc<RateLimiter>> {
+        let mut limiters: Vec<Arc<RateLimiter>> = self.rate_limiter.iter().cloned().collect();
+        if let Some(rate) = self.client_rate_limit {
+            let mut per_client = self.client_rate_limiters.lock().unwrap_or_else(|e| e.into_inner());
+            // Limiters no response holds and that refilled completely carry no state
+            per_client.retain(|_, limiter| Arc::strong_count(limiter) > 1 || !limiter.is_idle());
+            limiters.push(per_client
+                .entry(client)
+                .or_insert_with(|| Arc::new(RateLimiter::new(rate)))
+                .clone());
+        }
+
+        limiters
+    }
+}
+
+/// IPv4 network interfaces, excluding loopback, a server can bind to
+pub fn network_interfaces() -> Result<Vec<NetworkInterface>, Error> {
+    let interfaces = NetworkInterface::show()
+        .map_err(|e| Error::GeneralError(format!("Failed enumerating network interfaces: {e}")))?
+        .into_iter()
+        .filter(|intf|
+            intf.addr.iter().any(|&addr| addr.ip().is_ipv4() && !addr.ip().is_loopback())
+        )
+        .collect();
+
+    Ok(interfaces)
+}
+
+/// Ask on stdin which of `interfaces` to bind to, until a valid choice is read
+pub fn choose_bind_addr(interfaces: &[NetworkInterface], mut input: impl BufRead) -> Result<Ipv4Addr, Error> {
+    let choices: Vec<(&str, Ipv4Addr)> = interfaces
+        .iter()
+        .filter_map(|intf| {
+            intf.addr.iter().find_map(|addr| match addr.ip() {
+                IpAddr::V4(ip4_addr) => Some((intf.name.as_str(), ip4_addr)),
+                _ => None,
+            })
+        })
+        .collect();
+    if choices.is_empty() {
+        return Err(Error::GeneralError("No IPv4 network interfaces to bind to".into()));
+    }
+
+    loop {
+        for (idx, (name, addr)) in choices.iter().enumerate() {
+            println!("{idx}) {name} ({addr})")
+        }
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Err(Error::GeneralError("No network interface chosen".into()));
+        }
+
+        match line.trim().parse::<usize>().ok().and_then(|choice| choices.get(choice)) {
+            Some(&(_, addr)) => return Ok(addr),
+            None => log::error!("Invalid choice: {:?}, enter 0 to {}", line.trim(), choices.len() - 1),
+        }
+    }
+}
+
+/// Time responses in flight get to complete once a [`Server`] shuts down
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Builder for [`Server`]
+pub struct ServerBuilder {
+    address: Ipv4Addr,
+    port: u16,
+    name: String,
+    id: Option<String>,
+    provider: Option<Arc<dyn ContentProvider>>,
+    announce: bool,
+    rate_limit: Option<u64>,
+    client_rate_limit: Option<u64>,
+    access: AccessPolicy,
+    admin_address: Option<SocketAddr>,
+    admin_token: Option<String>,
+    drain_timeout: Duration,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self {
+            address: Ipv4Addr::UNSPECIFIED,
+            port: SERVER_PORT,
+            name: "XBOXTEST".into(),
+            id: None,
+            provider: None,
+            announce: true,
+            rate_limit: None,
+            client_rate_limit: None,
+            access: AccessPolicy::default(),
+            admin_address: None,
+            admin_token: None,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+        }
+    }
+}
+
+impl ServerBuilder {
+    /// Address to bind to and announce, required when announcing
+    pub fn address(mut self, address: Ipv4Addr) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// Port to bind to, defaults to [`SERVER_PORT`], `0` picks a free one
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Advertised console name
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_owned();
+        self
+    }
+
+    /// Console id, a random one is generated by default
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(id.to_owned());
+        self
+    }
+
+    /// Backend listing and serving content, nothing is offered by default
+    pub fn content_provider(mut self, provider: Arc<dyn ContentProvider>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    /// Offer local files, shorthand for a [`FileSystemProvider`]
+    pub fn items(self, items: Vec<PushItem>) -> Self {
+        self.content_provider(Arc::new(FileSystemProvider::new(items)))
+    }
+
+    /// Announce the server via mDNS while running, on by default
+    pub fn announce(mut self, announce: bool) -> Self {
+        self.announce = announce;
+        self
+    }
+
+    /// Limit total content bandwidth, bytes per second
+    pub fn rate_limit(mut self, rate: Option<u64>) -> Self {
+        self.rate_limit = rate;
+        self
+    }
+
+    /// Limit content bandwidth per client address, bytes per second
+    pub fn client_rate_limit(mut self, rate: Option<u64>) -> Self {
+        self.client_rate_limit = rate;
+        self
+    }
+
+    /// Admit only clients passing `access`, denied requests get `403 Forbidden`
+    pub fn access_policy(mut self, access: AccessPolicy) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Serve the admin endpoints, see [`crate::admin`], on `address`, off by default
+    ///
+    /// Only loopback addresses are accepted unless an admin token is set.
+    pub fn admin_address(mut self, address: Option<SocketAddr>) -> Self {
+        self.admin_address = address;
+        self
+    }
+
+    /// Require `Authorization: Bearer <token>` on every admin request
+    pub fn admin_token(mut self, token: Option<String>) -> Self {
+        self.admin_token = token;
+        self
+    }
+
+    /// Time responses in flight get to complete on shutdown, defaults to [`DEFAULT_DRAIN_TIMEOUT`]
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Bind the listening socket, serving starts with [`Server::run`]
+    pub fn build(self) -> Result<Server, Error> {
+        if self.announce && self.address.is_unspecified() {
+            return Err(Error::GeneralError("Announcing requires a bind address".into()));
+        }
+
+        let listener = TcpListener::bind((self.address, self.port))?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+        let admin_listener = match self.admin_address {
+            Some(address) if !address.ip().is_loopback() && self.admin_token.is_none() => {
+                return Err(Error::GeneralError(format!("Admin endpoints on non-loopback address {address} require a token")));
+            }
+            Some(address) => {
+                let listener = TcpListener::bind(address)?;
+                listener.set_nonblocking(true)?;
+                Some(listener)
+            }
+            None => None,
+        };
+        let admin_addr = admin_listener.as_ref().map(TcpListener::local_addr).transpose()?;
+
+        let console = Console {
+            address: self.address,
+            port: local_addr.port(),
+            id: self.id.unwrap_or_else(generate_random_console_id),
+            name: self.name,
+        };
+
+        Ok(Server {
+            local_addr,
+            drain_timeout: self.drain_timeout,
+            announce: self.announce,
+            state: Arc::new(AppState::new(
+                self.provider.unwrap_or_else(|| Arc::new(MemoryProvider::default())),
+                self.rate_limit,
+                self.client_rate_limit,
+            ).with_access_policy(self.access).with_console(console)),
+            admin_addr,
+            admin_token: self.admin_token,
+            listeners: Arc::new(Mutex::new(Some(Listeners {
+                content: listener,
+                admin: admin_listener,
+            }))),
+            shutdown: CancellationToken::new(),
+        })
+    }
+}
+
+/// Sockets bound by [`ServerBuilder::build`], taken by [`Server::run`]
+struct Listeners {
+    content: TcpListener,
+    admin: Option<TcpListener>,
+}
+
+/// Emulated console, cheap to clone so [`Server::shutdown`] can be called
+/// while [`Server::run`] is pending
+#[derive(Clone)]
+pub struct Server {
+    local_addr: SocketAddr,
+    drain_timeout: Duration,
+    announce: bool,
+    admin_addr: Option<SocketAddr>,
+    admin_token: Option<String>,
+    state: Arc<AppState>,
+    listeners: Arc<Mutex<Option<Listeners>>>,
+    shutdown: CancellationToken,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// Identity the server announces, see [`AppState::rename`]
+    pub fn console(&self) -> Console {
+        self.state.console().expect("Server state has a console")
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Address of the admin endpoints, if enabled
+    pub fn admin_addr(&self) -> Option<SocketAddr> {
+        self.admin_addr
+    }
+
+    pub fn state(&self) -> &Arc<AppState> {
+        &self.state
+    }
+
+    /// Serve until [`Server::shutdown`] is called
+    ///
+    /// On shutdown the server stops accepting connections and withdraws its
+    /// mDNS announcement, responses in flight get the drain timeout to
+    /// complete before they are abandoned. A server runs only once.
+    pub async fn run(&self) -> Result<(), Error> {
+        let Listeners { content: listener, admin: admin_listener } = self.listeners
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+            .ok_or(Error::GeneralError("Server already running".into()))?;
+
+        let server = axum::Server::from_tcp(listener)
+            .map_err(|e| Error::GeneralError(format!("Failed listening on {}: {e}", self.local_addr)))?
+            .http1_title_case_headers(true)
+            .serve(router(self.state.clone()).into_make_service_with_connect_info::<SocketAddr>());
+
+        if self.announce {
+            self.state.announce()?;
+        }
+
+        let admin = async {
+            let Some(listener) = admin_listener else {
+                return Ok(());
+            };
+            if let Some(addr) = self.admin_addr {
+                log::info!("Serving admin endpoints @ {addr}");
+            }
+            axum::Server::from_tcp(listener)?
+                .serve(admin::router(self.state.clone(), self.admin_token.clone()).into_make_service())
+                .with_graceful_shutdown(self.shutdown.cancelled())
+                .await
+        };
+
+        log::info!("Running HTTP Server @ {}", self.local_addr);
+        let serving = async {
+            tokio::try_join!(
+                server.with_graceful_shutdown(self.shutdown.cancelled()),
+                admin,
+            )
+        };
+        tokio::pin!(serving);
+
+        let result = tokio::select! {
+            result = &mut serving => result,
+            _ = self.shutdown.cancelled() => {
+                // Consoles should forget the server while responses drain
+                let state = self.state.clone();
+                match tokio::task::spawn_blocking(move || state.withdraw()).await {
+                    Ok(Err(e)) => log::warn!("Failed withdrawing announcement: {e:?}"),
+                    Err(e) => log::warn!("Failed withdrawing announcement: {e:?}"),
+                    Ok(Ok(())) => {}
+                }
+
+                match tokio::time::timeout(self.drain_timeout, &mut serving).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        log::warn!("Responses still in flight after {:?}, abandoning them", self.drain_timeout);
+                        Ok(((), ()))
+                    }
+                }
+            }
+        };
+
+        self.state.withdraw()?;
+
+        result
+            .map(|_| ())
+            .map_err(|e| Error::GeneralError(format!("HTTP server failed: {e}")))
+    }
+
+    /// Serve until the console pulled every one of `items`, within `timeout` in total
+    ///
+    /// The server stops gracefully once pushed, so the last response is fully
+    /// delivered. Shutting down before that fails the push.
+    pub async fn push(&self, items: &[PushItem], timeout: Option<Duration>) -> Result<(), Error> {
+        let serving = self.run();
+        let pulled = self.state.tracker().wait_for_items(items, timeout);
+        tokio::pin!(serving, pulled);
+
+        tokio::select! {
+            served = &mut serving => {
+                served?;
+                Err(Error::GeneralError("Push interrupted before the console pulled everything".into()))
+            }
+            pulled = &mut pulled => {
+                self.shutdown();
+                serving.await?;
+                pulled
+            }
+        }
+    }
+
+    /// Stop accepting connections and let [`Server::run`] return
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+}
+
+/// Resolve once SIGINT or, on Unix, SIGTERM is received
+pub async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::error!("Failed listening for SIGINT: {e:?}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                log::error!("Failed listening for SIGTERM: {e:?}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => log::info!("Received SIGINT, shutting down"),
+        _ = terminate => log::info!("Received SIGTERM, shutting down"),
+    }
+}
+
+/// Router answering like a console, serve it with
+/// `into_make_service_with_connect_info::<SocketAddr>()` and
+/// `http1_title_case_headers(true)`
+pub fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/col/metadata", get(get_metadata))
+        .route("/col/content/:filename", get(get_content))
+        .fallback(fallback_handler)
+        .layer(middleware::from_fn_with_state(state.clone(), check_access))
+        .layer(middleware::from_fn_with_state(state.clone(), log_access))
+        .with_state(state)
+}
+
+/// Log every request once its response went out and count it in the [`Metrics`]
+async fn log_access(State(state): State<Arc<AppState>>, ConnectInfo(client): ConnectInfo<SocketAddr>, request: Request<Body>, next: Next<Body>) -> Response {
+    let mut entry = AccessEntry::new(state.metrics.clone(), client, &request);
+    let response = next.run(request).await;
+    entry.respond(&response);
+
+    response.map(|body| boxed(LoggedBody::new(body, entry)))
+}
+
+/// Reject clients not admitted by the [`AccessPolicy`] with `403 Forbidden`
+async fn check_access(State(state): State<Arc<AppState>>, ConnectInfo(client): ConnectInfo<SocketAddr>, request: Request<Body>, next: Next<Body>) -> Response {
+    let user_agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+
+    match state.access.check(client.ip(), user_agent.as_deref()) {
+        Ok(()) => next.run(request).await,
+        Err(denied) => {
+            log::warn!("Denied {} {} from {client} ({user_agent:?}): {denied}", request.method(), request.uri());
+            (StatusCode::FORBIDDEN, [("Server", SERVER)]).into_response()
+        }
+    }
+}
+
+/*
+Handlers
+*/
+async fn fallback_handler(request: Request<Body>) -> Response {
+    log::debug!("Unknown request: {} {}", request.method(), request.uri());
+    (StatusCode::NOT_FOUND, [("Server", SERVER)]).into_response()
+}
+
+/// Get metadata
+/// 
+/// ```text
+/// ORIGINAL
+/// < HTTP/1.1 200 OK
+/// < Content-Type: text/json
+/// < Server: Microsoft-HTTPAPI/2.0
+/// < Date: Sun, 08 Oct 2023 00:33:34 GMT
+/// < Content-Length: 141092
+/// 
+/// OWN
+/// < HTTP/1.1 200 OK
+/// < content-type: text/json
+/// < server: Microsoft-HTTPAPI/2.0
+/// < content-length: 469
+/// < date: Sun, 08 Oct 2023 00:35:53 GMT
+/// ```
+async fn get_metadata(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    let version = match negotiate_contract_version(&headers) {
+        Ok(version) => version,
+        Err(requested) => return reject_contract_version(requested),
+    };
+    log::debug!("Metadata requested with contract version {version}");
+
+    // Every supported version gets its own arm, negotiation rejected the rest
+    let listed = match version {
+        ContractVersion::V1 => state.provider.list().await,
+        unknown => return reject_contract_version(unknown.to_string()),
+    };
+    let metadata = match listed {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            log::error!("Failed listing content: {e:?}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    // Serialize the struct directly, going through `json!` would sort the keys
+    let body = Json(metadata);
+
+    (
+        [
+            ("Content-type", "text/json"),
+            ("Server", "Microsoft-HTTPAPI/2.0"),
+            (ContractVersion::HEADER, &version.to_string()),
+        ],
+        body
+    ).into_response()
+}
+
+/// Contract version requested via `x-contract-version`, version 1 if absent
+///
+/// Returns the raw header value if it is unparsable or unsupported.
+fn negotiate_contract_version(headers: &HeaderMap) -> Result<ContractVersion, String> {
+    let requested = match headers.get(ContractVersion::HEADER) {
+        Some(value) => String::from_utf8_lossy(value.as_bytes()).into_owned(),
+        None => return Ok(ContractVersion::default()),
+    };
+
+    match requested.parse::<ContractVersion>() {
+        Ok(version) if version.is_supported() => Ok(version),
+        _ => Err(requested),
+    }
+}
+
+/// `400 Bad Request` listing the supported versions in header and body
+fn reject_contract_version(requested: String) -> Response {
+    log::warn!("Rejecting unsupported contract version: {requested:?}");
+    let supported: Vec<u32> = ContractVersion::SUPPORTED.iter().map(|v| v.0).collect();
+    let supported_header = supported.iter().map(u32::to_string).collect::<Vec<_>>().join(",");
+    let body = Json(ContractVersionError {
+        error: "unsupportedContractVersion".into(),
+        requested,
+        supported,
+    });
+
+    (
+        StatusCode::BAD_REQUEST,
+        [
+            (ContractVersion::HEADER, supported_header.as_str()),
+            ("Server", "Microsoft-HTTPAPI/2.0"),
+        ],
+        body,
+    ).into_response()
+}
+
+/// Get content
+///
+/// Mirrors the console's response, see [`crate::content`].
+///
+/// ```text
+/// ORIGINAL
+/// 
+/// < HTTP/1.1 206 OK
+/// < Content-Type: application/octet-stream
+/// < Content-Range: bytes 0-0/105205760
+/// < Server: Microsoft-HTTPAPI/2.0
+/// < Date: Sun, 08 Oct 2023 00:18:01 GMT
+/// < Content-Length: 1
+/// ```
+async fn get_content(State(state): State<Arc<AppState>>, ConnectInfo(client): ConnectInfo<SocketAddr>, Path(filename): Path<String>, headers: HeaderMap) -> Response
+{
+    let range = match headers.get("range").map(|value| value.to_str().unwrap_or_default().parse::<RangeRequest>()) {
+        Some(Ok(range)) => Some(range),
+        Some(Err(e)) => {
+            // Unusable range headers are ignored, serving the full content
+            log::warn!("Ignoring range header of {filename}: {e}");
+            None
+        }
+        None => None,
+    };
+    log::debug!("Content requested: {filename} {range:?}");
+
+    let content_path: ContentPath = match filename.parse() {
+        Ok(content_path) => content_path,
+        Err(e) => {
+            log::warn!("Invalid content request {filename}: {e:?}");
+            return StatusCode::NOT_FOUND.into_response();
+        }
+    };
+
+    let if_range = headers.get(header::IF_RANGE).map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+    let (response, served) = match provider_response(state.provider.as_ref(), &content_path, range, if_range.as_deref()).await {
+        Ok(response) => response,
+        Err(Error::NotFound(path)) => {
+            log::warn!("Content not found: {path}");
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        Err(e) => {
+            log::error!("Failed serving {filename}: {e:?}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let limiters = state.rate_limiters(client.ip());
+    let mut response = match limiters.is_empty() {
+        true => response,
+        false => response.map(|body| boxed(ThrottledBody::new(body, limiters))),
+    };
+
+    let item = state.provider.canonical_path(&content_path);
+    let path = item.to_url_path();
+    response.extensions_mut().insert(ServedItem(item));
+    match served {
+        Some(served) => response.map(|body| {
+            boxed(TrackedBody::new(body, state.tracker.clone(), &path, served.first() as u64, served.last() as u64))
+        }),
+        None => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::provider::MemoryProvider;
+
+    #[test]
+    fn test_choose_bind_addr() {
+        let interfaces = vec![
+            NetworkInterface::new_afinet6("eth1", "fe80::1".parse().unwrap(), None, None, 2),
+            NetworkInterface::new_afinet("eth0", Ipv4Addr::new(192, 168, 1, 10), None, None, 1),
+            NetworkInterface::new_afinet("wlan0", Ipv4Addr::new(10, 0, 0, 5), None, None, 3),
+        ];
+
+        // Only IPv4 interfaces are offered, invalid choices are asked again
+        let addr = choose_bind_addr(&interfaces, "x\n7\n1\n".as_bytes()).unwrap();
+        assert_eq!(addr, Ipv4Addr::new(10, 0, 0, 5));
+
+        assert!(choose_bind_addr(&interfaces, "5\n".as_bytes()).is_err());
+        assert!(choose_bind_addr(&interfaces[..1], "0\n".as_bytes()).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_push_stops_once_pulled() {
+        let file = std::env::temp_dir().join(format!("push-{}.appx", Uuid::new_v4()));
+        std::fs::write(&file, [7u8; 100]).unwrap();
+        let items = vec![PushItem::from_file(&file, Uuid::new_v4()).unwrap()];
+        let server = Server::builder()
+            .items(items.clone())
+            .address(Ipv4Addr::LOCALHOST)
+            .port(0)
+            .announce(false)
+            .build()
+            .unwrap();
+
+        let addr = server.local_addr();
+        let item = items[0].item.clone();
+        let pulling = tokio::task::spawn_blocking(move || {
+            let mut content = vec![];
+            crate::Client::new(&addr.ip().to_string(), addr.port()).download_chunks(&item, item.size, &mut content, 40)
+        });
+
+        server.push(&items, Some(Duration::from_secs(10))).await.unwrap();
+        assert_eq!(pulling.await.unwrap().unwrap(), 100);
+
+        // Shut down before anything was pulled
+        let server = Server::builder()
+            .items(items.clone())
+            .address(Ipv4Addr::LOCALHOST)
+            .port(0)
+            .announce(false)
+            .build()
+            .unwrap();
+        server.shutdown();
+        assert!(server.push(&items, None).await.is_err());
+
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_idle_client_limiters_are_evicted() {
+        let state = AppState::new(Arc::new(MemoryProvider::new(Uuid::new_v4())), None, Some(1_000_000));
+        let client_limiters = |state: &AppState| state.client_rate_limiters.lock().unwrap().len();
+
+        let held = state.rate_limiters("10.0.0.1".parse().unwrap());
+        held[0].reserve(1_000_000);
+        drop(state.rate_limiters("10.0.0.2".parse().unwrap()));
+        assert_eq!(client_limiters(&state), 2);
+
+        // The first client is still throttled, the second left no debt behind
+        state.rate_limiters("10.0.0.3".parse().unwrap());
+        assert_eq!(client_limiters(&state), 2);
+        drop(held);
+    }
+}
@@ -0,0 +1,86 @@
+//! Statistics of a completed download.
+//!
+//! [`Client::download_chunks_adaptive`](crate::Client::download_chunks_adaptive)
+//! returns a [`TransferSummary`]. It prints as a single line and serializes
+//! to JSON for tracking LAN performance over time.
+use std::{fmt, time::Duration};
+
+use indicatif::HumanBytes;
+use serde::{Serialize, Serializer};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferSummary {
+    /// Name of the console, its address if the client was not given one
+    pub console: String,
+    /// Package family name of the item
+    pub item: String,
+    /// Bytes downloaded, excluding any resume offset
+    pub bytes: usize,
+    #[serde(rename = "elapsedSecs", serialize_with = "serialize_secs")]
+    pub elapsed: Duration,
+    /// Bytes per second over the whole transfer
+    pub average_throughput: f64,
+    /// Bytes per second of the fastest chunk
+    pub peak_throughput: f64,
+    /// Chunks requested again after a failure
+    pub retries: usize,
+    /// Chunks downloaded successfully
+    pub chunks: usize,
+}
+
+impl TransferSummary {
+    /// Summary of `bytes` moved in `chunks` within `elapsed`, average throughput derived
+    pub fn new(console: &str, item: &str, bytes: usize, elapsed: Duration, peak_throughput: f64, retries: usize, chunks: usize) -> Self {
+        Self {
+            console: console.to_owned(),
+            item: item.to_owned(),
+            bytes,
+            elapsed,
+            average_throughput: bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+            peak_throughput,
+            retries,
+            chunks,
+        }
+    }
+}
+
+fn serialize_secs<S: Serializer>(elapsed: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(elapsed.as_secs_f64())
+}
+
+impl fmt::Display for TransferSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} from {} in {:.1}s, average {}/s, peak {}/s, {} chunk{}, {} {}",
+            self.item,
+            HumanBytes(self.bytes as u64),
+            self.console,
+            self.elapsed.as_secs_f64(),
+            HumanBytes(self.average_throughput as u64),
+            HumanBytes(self.peak_throughput as u64),
+            self.chunks,
+            if self.chunks == 1 { "" } else { "s" },
+            self.retries,
+            if self.retries == 1 { "retry" } else { "retries" },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_output() {
+        let summary = TransferSummary::new("XBOX", "game", 3 << 20, Duration::from_secs(2), 2.0 * (1 << 20) as f64, 1, 4);
+        assert_eq!(summary.average_throughput, (3 << 19) as f64);
+        assert_eq!(summary.to_string(), "game: 3.00 MiB from XBOX in 2.0s, average 1.50 MiB/s, peak 2.00 MiB/s, 4 chunks, 1 retry");
+
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["elapsedSecs"], 2.0);
+        assert_eq!(json["averageThroughput"], (3 << 19) as f64);
+        assert_eq!(json["console"], "XBOX");
+    }
+}
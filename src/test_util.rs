@@ -0,0 +1,108 @@
+//! Mock console for integration tests, enabled by the `test-util` feature.
+//!
+//! [`MockConsole`] generates a library of random content files, serves it
+//! with a [`Server`] on an ephemeral localhost port and shuts down when
+//! dropped. No mDNS is involved.
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::JoinHandle,
+};
+
+use rand::RngCore;
+use uuid::Uuid;
+
+use crate::{error::Error, provider::FileSystemProvider, push::PushItem, server::ServerBuilder, Client, Server};
+
+pub struct MockConsole {
+    server: Server,
+    dir: PathBuf,
+    items: Vec<PushItem>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockConsole {
+    /// Serve a generated library of `(file name, size)` items
+    pub fn start(library: &[(&str, usize)]) -> Result<Self, Error> {
+        Self::start_with(library, |builder| builder)
+    }
+
+    /// Like [`MockConsole::start`], `configure` adjusts the server beforehand
+    pub fn start_with(library: &[(&str, usize)], configure: impl FnOnce(ServerBuilder) -> ServerBuilder) -> Result<Self, Error> {
+        let dir = std::env::temp_dir().join(format!("mock-console-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir)?;
+
+        let drive_id = Uuid::new_v4();
+        let mut items = vec![];
+        for (name, size) in library {
+            let mut content = vec![0u8; *size];
+            rand::thread_rng().fill_bytes(&mut content);
+            let file = dir.join(name);
+            std::fs::write(&file, content)?;
+            items.push(PushItem::from_file(file, drive_id)?);
+        }
+        let provider = FileSystemProvider::new(items.clone()).with_root(&dir);
+        let builder = configure(Server::builder().content_provider(Arc::new(provider)));
+        let server = builder
+            .address(Ipv4Addr::LOCALHOST)
+            .port(0)
+            .announce(false)
+            .build()?;
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()?;
+        let running = server.clone();
+        let thread = std::thread::spawn(move || {
+            if let Err(e) = runtime.block_on(running.run()) {
+                log::error!("Mock console failed: {e:?}");
+            }
+        });
+
+        Ok(Self {
+            server,
+            dir,
+            items,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.server.local_addr()
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    /// Library directory holding the generated content
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn items(&self) -> &[PushItem] {
+        &self.items
+    }
+
+    /// Client connected to this console
+    pub fn client(&self) -> Client {
+        Client::new(&self.addr().ip().to_string(), self.addr().port())
+    }
+
+    /// Generated content of the item named `name`
+    pub fn content(&self, name: &str) -> Vec<u8> {
+        std::fs::read(self.dir.join(name)).expect("Item not generated")
+    }
+}
+
+impl Drop for MockConsole {
+    fn drop(&mut self) {
+        self.server.shutdown();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,218 @@
+//! Bandwidth limiting.
+//!
+//! [`RateLimiter`] is a token bucket refilled at a fixed number of bytes per
+//! second, holding at most [`BURST`] worth of tokens. Callers take what they
+//! need up front and wait off any resulting debt, so chunks larger than the
+//! bucket still pass, just delayed accordingly.
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::body::Bytes;
+use http_body::Body;
+use tokio::time::Sleep;
+
+use crate::{chunking::parse_size, error::Error};
+
+/// Time worth of tokens a bucket holds, also what a new limiter starts with
+pub const BURST: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: u64,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1);
+        Self {
+            bytes_per_sec,
+            bucket: Mutex::new(Bucket {
+                tokens: Self::burst(bytes_per_sec),
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    /// Bucket capacity, at least a byte
+    fn burst(bytes_per_sec: u64) -> f64 {
+        (bytes_per_sec as f64 * BURST.as_secs_f64()).max(1.0)
+    }
+
+    /// Whether the bucket refilled completely, so a new limiter would behave the same
+    pub fn is_idle(&self) -> bool {
+        let bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let refill = bucket.last_refill.elapsed().as_secs_f64() * self.bytes_per_sec as f64;
+        bucket.tokens + refill >= Self::burst(self.bytes_per_sec)
+    }
+
+    /// Take `bytes` tokens, returning how long to wait before using them
+    pub fn reserve(&self, bytes: usize) -> Duration {
+        let rate = self.bytes_per_sec as f64;
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.last_refill).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(Self::burst(self.bytes_per_sec)) - bytes as f64;
+        bucket.last_refill = now;
+
+        match bucket.tokens < 0.0 {
+            true => Duration::from_secs_f64(-bucket.tokens / rate),
+            false => Duration::ZERO,
+        }
+    }
+
+    /// Blocking wait for `bytes` tokens
+    pub fn acquire_blocking(&self, bytes: usize) {
+        let wait = self.reserve(bytes);
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Async wait for `bytes` tokens
+    pub async fn acquire(&self, bytes: usize) {
+        let wait = self.reserve(bytes);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Longest wait required by any of `limiters` for `bytes`
+pub fn reserve_all(limiters: &[Arc<RateLimiter>], bytes: usize) -> Duration {
+    limiters
+        .iter()
+        .map(|limiter| limiter.reserve(bytes))
+        .max()
+        .unwrap_or_default()
+}
+
+/// Parse a rate in bytes per second, accepting the same sizes as [`parse_size`]
+pub fn parse_rate(rate: &str) -> Result<u64, Error> {
+    parse_size(rate)
+        .map(|bytes| bytes as u64)
+        .map_err(|_| Error::GeneralError(format!("Invalid rate: {}", rate.trim())))
+}
+
+/// Response body throttled by one or more [`RateLimiter`]s
+pub struct ThrottledBody<B> {
+    inner: B,
+    limiters: Vec<Arc<RateLimiter>>,
+    pending: Option<(Bytes, Pin<Box<Sleep>>)>,
+}
+
+impl<B> ThrottledBody<B> {
+    pub fn new(inner: B, limiters: Vec<Arc<RateLimiter>>) -> Self {
+        Self {
+            inner,
+            limiters,
+            pending: None,
+        }
+    }
+}
+
+impl<B> Body for ThrottledBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if let Some((_, sleep)) = self.pending.as_mut() {
+            if sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            let (chunk, _) = self.pending.take().expect("pending chunk");
+            return Poll::Ready(Some(Ok(chunk)));
+        }
+
+        match Pin::new(&mut self.inner).poll_data(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                let wait = reserve_all(&self.limiters, chunk.len());
+                if wait.is_zero() {
+                    return Poll::Ready(Some(Ok(chunk)));
+                }
+
+                let mut sleep = Box::pin(tokio::time::sleep(wait));
+                match sleep.as_mut().poll(cx) {
+                    Poll::Ready(()) => Poll::Ready(Some(Ok(chunk))),
+                    Poll::Pending => {
+                        self.pending = Some((chunk, sleep));
+                        Poll::Pending
+                    }
+                }
+            }
+            other => other,
+        }
+    }
+
+    fn poll_trailers(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<axum::http::HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pending.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("1000").unwrap(), 1000);
+        assert_eq!(parse_rate("512K").unwrap(), 512 * 1024);
+        assert_eq!(parse_rate("10m").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(parse_rate("1G").unwrap(), 1024 * 1024 * 1024);
+        assert_eq!(parse_rate("10MiB").unwrap(), 10 * 1024 * 1024);
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("fast").is_err());
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::new(1000);
+        // Burst of 100ms is free, everything beyond is debt
+        assert_eq!(limiter.reserve(100), Duration::ZERO);
+        assert!(!limiter.is_idle());
+        let wait = limiter.reserve(500);
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500), "{wait:?}");
+
+        let limiter = RateLimiter::new(1_000_000);
+        assert!(limiter.is_idle());
+        assert!(limiter.reserve(200_000) > Duration::from_millis(50));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttled_body() {
+        let limiter = Arc::new(RateLimiter::new(100));
+        let body = axum::body::Full::new(Bytes::from(vec![0u8; 300]));
+        let started = tokio::time::Instant::now();
+
+        let data = hyper::body::to_bytes(ThrottledBody::new(body, vec![limiter])).await.unwrap();
+        assert_eq!(data.len(), 300);
+        assert!(started.elapsed() >= Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,277 @@
+//! Packages stored in pieces, served as one file.
+//!
+//! Archived packages are kept as split files (`name.001`, `name.002`, ...)
+//! or as entries of a tar archive. A [`VirtualFile`] maps the bytes of such
+//! a package onto byte spans of local files, so a range can be read across
+//! part boundaries without the console noticing.
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+
+use crate::{error::Error, Range};
+
+/// Extension of the first part of a split file
+pub const FIRST_PART: &str = "001";
+
+/// Length and modification time of a local file, telling versions apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileStamp {
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    pub fn of(metadata: &std::fs::Metadata) -> Self {
+        Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        }
+    }
+
+    /// Strong entity tag of the file's content
+    pub fn etag(&self) -> String {
+        entity_tag(self)
+    }
+}
+
+/// Quoted entity tag for the content version identified by `version`
+pub fn entity_tag(version: impl Hash) -> String {
+    let mut hasher = DefaultHasher::new();
+    version.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Bytes `offset..offset + len` of a local file, stamped as it was when listed
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Segment {
+    pub path: PathBuf,
+    pub offset: usize,
+    pub len: usize,
+    pub stamp: FileStamp,
+}
+
+/// Concatenation of file segments read as a single file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VirtualFile {
+    segments: Vec<Segment>,
+}
+
+impl VirtualFile {
+    pub fn new(segments: Vec<Segment>) -> Self {
+        Self { segments }
+    }
+
+    /// Parts `base.001`, `base.002`, ... of the split file starting at `first_part`
+    ///
+    /// Parts are numbered consecutively, the first gap ends the file.
+    pub fn split(first_part: impl AsRef<Path>) -> Result<Self, Error> {
+        let first_part = first_part.as_ref();
+        if split_base(first_part).is_none() {
+            return Err(Error::GeneralError(format!("Not the first part of a split file: {first_part:?}")));
+        }
+
+        let width = FIRST_PART.len();
+        let mut segments = vec![];
+        for number in 1.. {
+            let path = first_part.with_extension(format!("{number:0width$}"));
+            let stamp = match std::fs::metadata(&path) {
+                Ok(metadata) if metadata.is_file() => FileStamp::of(&metadata),
+                _ => break,
+            };
+            segments.push(Segment { path, offset: 0, len: stamp.len as usize, stamp });
+        }
+
+        Ok(Self::new(segments))
+    }
+
+    /// Regular file entries of the tar archive at `archive`, by entry file name
+    ///
+    /// Only the archive headers are read, entry data stays in the archive.
+    /// Entries are offered by file name, so archives with two entries of the
+    /// same file name, in different directories or appended twice, are
+    /// rejected.
+    pub fn tar_entries(archive: impl AsRef<Path>) -> Result<Vec<(String, Self)>, Error> {
+        let archive = archive.as_ref();
+        let file = std::fs::File::open(archive)?;
+        let stamp = FileStamp::of(&file.metadata()?);
+        let mut entries = vec![];
+        for entry in tar::Archive::new(file).entries()? {
+            let entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let path = entry.path()?;
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+
+            if entries.iter().any(|(entry_name, _)| entry_name == name) {
+                return Err(Error::GeneralError(format!("Duplicate entry name {name} in {archive:?}")));
+            }
+
+            let segment = Segment {
+                path: archive.to_path_buf(),
+                offset: entry.raw_file_position() as usize,
+                len: entry.size() as usize,
+                stamp,
+            };
+            entries.push((name.to_string(), Self::new(vec![segment])));
+        }
+
+        Ok(entries)
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn size(&self) -> usize {
+        self.segments.iter().map(|segment| segment.len).sum()
+    }
+
+    /// Strong entity tag, changing with any of the files
+    pub fn etag(&self) -> String {
+        entity_tag(&self.segments)
+    }
+
+    /// Reader for `range`, chaining the segments it covers
+    ///
+    /// Fails if a file covered changed since its segment was stamped, reading
+    /// at the listed offsets would mix versions.
+    pub async fn open(&self, range: Range) -> Result<Box<dyn AsyncRead + Send + Unpin>, Error> {
+        if range.last() >= self.size() {
+            return Err(Error::InvalidRange(format!("{range} beyond size {}", self.size())));
+        }
+
+        let mut reader: Box<dyn AsyncRead + Send + Unpin> = Box::new(tokio::io::empty());
+        let mut start = 0;
+        for segment in &self.segments {
+            let end = start + segment.len;
+            if start <= range.last() && range.first() < end {
+                let first = range.first().max(start) - start;
+                let last = range.last().min(end - 1) - start;
+
+                let mut file = tokio::fs::File::open(&segment.path).await?;
+                if FileStamp::of(&file.metadata().await?) != segment.stamp {
+                    return Err(Error::GeneralError(format!("{:?} changed on disk, not yet reloaded", segment.path)));
+                }
+                file.seek(SeekFrom::Start((segment.offset + first) as u64)).await?;
+                reader = Box::new(reader.chain(file.take((last - first + 1) as u64)));
+            }
+            start = end;
+        }
+
+        Ok(reader)
+    }
+}
+
+/// Name of the split file `path` is the first part of, `app.appx.001` gives `app.appx`
+pub fn split_base(path: &Path) -> Option<&str> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(FIRST_PART) => path.file_stem().and_then(|stem| stem.to_str()),
+        _ => None,
+    }
+}
+
+/// Whether `path` is a tar archive by its extension
+pub fn is_tar(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("tar"))
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    async fn read(file: &VirtualFile, first: usize, last: usize) -> Vec<u8> {
+        let mut data = vec![];
+        let mut reader = file.open(Range::new(first, last).unwrap()).await.unwrap();
+        reader.read_to_end(&mut data).await.unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn test_split_across_parts() {
+        let dir = std::env::temp_dir().join(format!("vfs-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (part, data) in [("001", "012"), ("002", "3456"), ("003", "789"), ("005", "gap")] {
+            std::fs::write(dir.join(format!("app.appx.{part}")), data).unwrap();
+        }
+
+        let first = dir.join("app.appx.001");
+        assert_eq!(split_base(&first), Some("app.appx"));
+        assert_eq!(split_base(&dir.join("app.appx.002")), None);
+
+        let file = VirtualFile::split(&first).unwrap();
+        assert_eq!(file.segments().len(), 3);
+        assert_eq!(file.size(), 10);
+        assert_eq!(read(&file, 0, 9).await, b"0123456789");
+        assert_eq!(read(&file, 2, 7).await, b"234567");
+        assert_eq!(read(&file, 3, 6).await, b"3456");
+        assert_eq!(read(&file, 9, 9).await, b"9");
+        assert!(file.open(Range::new(5, 10).unwrap()).await.is_err());
+
+        // A part changing length or content is noticed, until split again
+        let etag = file.etag();
+        std::fs::write(dir.join("app.appx.002"), "34567").unwrap();
+        assert_eq!(read(&file, 0, 2).await, b"012");
+        assert!(file.open(Range::new(0, 3).unwrap()).await.is_err());
+        let file = VirtualFile::split(&first).unwrap();
+        assert_eq!(read(&file, 3, 7).await, b"34567");
+        assert_ne!(file.etag(), etag);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tar_entries() {
+        let dir = std::env::temp_dir().join(format!("vfs-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("archive.tar");
+
+        let mut builder = tar::Builder::new(std::fs::File::create(&archive).unwrap());
+        for (name, data) in [("games/first.xvc", &b"first entry"[..]), ("second.appx", &[7u8; 1000][..])] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data).unwrap();
+        }
+        builder.finish().unwrap();
+        drop(builder);
+
+        assert!(is_tar(&archive));
+        let entries = VirtualFile::tar_entries(&archive).unwrap();
+        let names: Vec<_> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["first.xvc", "second.appx"]);
+        assert_eq!(entries[0].1.size(), 11);
+        assert_eq!(read(&entries[0].1, 6, 10).await, b"entry");
+        assert_eq!(read(&entries[1].1, 0, 999).await, [7u8; 1000]);
+
+        // Rewritten in place, same size, the modification time tells
+        let rewritten = std::fs::OpenOptions::new().write(true).open(&archive).unwrap();
+        rewritten.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        assert!(entries[0].1.open(Range::new(0, 0).unwrap()).await.is_err());
+
+        let duplicates = dir.join("duplicates.tar");
+        let mut builder = tar::Builder::new(std::fs::File::create(&duplicates).unwrap());
+        for name in ["a/game.xvc", "b/game.xvc"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(1);
+            header.set_cksum();
+            builder.append_data(&mut header, name, &b"x"[..]).unwrap();
+        }
+        builder.finish().unwrap();
+        drop(builder);
+        assert!(VirtualFile::tar_entries(&duplicates).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+//! Hot reload of a served directory.
+//!
+//! [`LibraryWatcher`] watches the root of a [`FileSystemProvider`] and calls
+//! [`FileSystemProvider::reload`] once changes settle, so packages still being
+//! copied in are not listed with a partial size.
+use std::{
+    sync::{mpsc, Arc},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{error::Error, provider::FileSystemProvider};
+
+/// Quiet period after the last change before reloading
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Reloads a provider's library while alive
+pub struct LibraryWatcher {
+    watcher: Option<RecommendedWatcher>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LibraryWatcher {
+    /// Watch the root directory of `provider`, reloading `debounce` after the last change
+    pub fn start(provider: Arc<FileSystemProvider>, debounce: Duration) -> Result<Self, Error> {
+        let root = provider
+            .root()
+            .ok_or(Error::GeneralError("Provider has no library directory to watch".into()))?
+            .to_path_buf();
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        watcher.watch(&root, RecursiveMode::NonRecursive)?;
+        log::info!("Watching {root:?} for library changes");
+
+        let thread = std::thread::spawn(move || reload_on_change(&provider, &rx, debounce));
+
+        Ok(Self {
+            watcher: Some(watcher),
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Reload after each burst of events, until the watcher is dropped
+fn reload_on_change(provider: &FileSystemProvider, rx: &mpsc::Receiver<notify::Result<notify::Event>>, debounce: Duration) {
+    while let Ok(event) = rx.recv() {
+        if let Err(e) = event {
+            log::warn!("Library watch error: {e:?}");
+            continue;
+        }
+        loop {
+            match rx.recv_timeout(debounce) {
+                Ok(_) => continue,
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+        }
+
+        match provider.reload() {
+            Ok(diff) if diff.is_empty() => log::debug!("Library reloaded, unchanged"),
+            Ok(diff) => log::info!(
+                "Library reloaded: {} added, {} removed, {} changed",
+                diff.added.len(), diff.removed.len(), diff.changed.len()
+            ),
+            Err(e) => log::error!("Failed reloading library: {e:?}"),
+        }
+    }
+}
+
+impl Drop for LibraryWatcher {
+    fn drop(&mut self) {
+        // Dropping the watcher closes the channel, ending the thread
+        self.watcher.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::provider::ContentProvider;
+
+    async fn listed(provider: &FileSystemProvider) -> Vec<(String, usize)> {
+        let metadata = provider.list().await.unwrap();
+        metadata.items.into_iter().map(|item| (item.package_family_name, item.size)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_reload_on_change() {
+        let dir = std::env::temp_dir().join(format!("watch-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("first.appx"), b"1").unwrap();
+
+        let provider = Arc::new(FileSystemProvider::from_dir(&dir, Uuid::new_v4()).unwrap());
+        let watcher = LibraryWatcher::start(provider.clone(), Duration::from_millis(100)).unwrap();
+
+        std::fs::write(dir.join("second.appx"), b"22").unwrap();
+        std::fs::write(dir.join("first.appx"), b"111").unwrap();
+        let expected = vec![("first".to_string(), 3), ("second".to_string(), 2)];
+        for _ in 0..50 {
+            if listed(&provider).await == expected {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(listed(&provider).await, expected);
+
+        drop(watcher);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+//! Compare content responses byte for byte against captured console responses.
+//!
+//! Reference responses live in `tests/fixtures/responses` as raw HTTP heads,
+//! the `Date` header is the only value allowed to differ.
+use std::{net::SocketAddr, path::{Path, PathBuf}};
+
+use axum::{extract::State, http::HeaderMap, response::Response, routing::get, Router};
+use network_transfer::{content::content_response, range::RangeRequest};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+async fn serve_file(State(file): State<PathBuf>, headers: HeaderMap) -> Response {
+    let range: Option<RangeRequest> = headers
+        .get("range")
+        .map(|value| value.to_str().unwrap().parse().unwrap());
+    let file = tokio::fs::File::open(&file).await.unwrap();
+    let total = file.metadata().await.unwrap().len() as usize;
+
+    content_response(file, total, range).await.unwrap().0
+}
+
+/// Serve a sparse file of `size` bytes, returning the server address and file
+async fn start_server(name: &str, size: u64) -> (SocketAddr, PathBuf) {
+    let path = std::env::temp_dir().join(format!("{}-{name}", std::process::id()));
+    std::fs::File::create(&path).unwrap().set_len(size).unwrap();
+
+    let app = Router::new()
+        .route("/col/content/:name", get(serve_file))
+        .with_state(path.clone());
+    let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap())
+        .http1_title_case_headers(true)
+        .serve(app.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+
+    (addr, path)
+}
+
+/// Send a raw request, returning the response head and body
+async fn request(addr: SocketAddr, range: Option<&str>) -> (String, Vec<u8>) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let range = range.map(|range| format!("Range: {range}\r\n")).unwrap_or_default();
+    let request = format!("GET /col/content/item HTTP/1.1\r\nHost: {addr}\r\n{range}Connection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = vec![];
+    stream.read_to_end(&mut response).await.unwrap();
+    let split = response.windows(4).position(|window| window == b"\r\n\r\n").unwrap() + 4;
+    let body = response.split_off(split);
+
+    (String::from_utf8(response).unwrap(), body)
+}
+
+fn mask_date(head: &str) -> String {
+    head.split("\r\n")
+        .map(|line| match line.starts_with("Date: ") {
+            true => "Date: <date>",
+            false => line,
+        })
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+fn reference(name: &str) -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/responses").join(name);
+    std::fs::read_to_string(path).unwrap()
+}
+
+#[tokio::test]
+async fn single_byte_range_matches_console() {
+    let (addr, file) = start_server("range-probe", 105205760).await;
+
+    let (head, body) = request(addr, Some("bytes=0-0")).await;
+    assert_eq!(mask_date(&head), mask_date(&reference("content_range_0-0.http")));
+    assert_eq!(body, [0]);
+    std::fs::remove_file(file).unwrap();
+}
+
+#[tokio::test]
+async fn full_and_unsatisfiable_ranges() {
+    let (addr, file) = start_server("full", 1000).await;
+
+    let (head, body) = request(addr, Some("bytes=990-")).await;
+    assert!(head.starts_with("HTTP/1.1 206 OK\r\n"), "{head}");
+    assert!(head.contains("\r\nContent-Range: bytes 990-999/1000\r\n"), "{head}");
+    assert!(!head.to_ascii_lowercase().contains("accept-ranges"), "{head}");
+    assert_eq!(body.len(), 10);
+
+    let (head, body) = request(addr, None).await;
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(!head.contains("Content-Range"), "{head}");
+    assert_eq!(body.len(), 1000);
+
+    let (head, _) = request(addr, Some("bytes=1000-")).await;
+    assert!(head.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"), "{head}");
+    assert!(head.contains("\r\nContent-Range: bytes */1000\r\n"), "{head}");
+    std::fs::remove_file(file).unwrap();
+}
@@ -0,0 +1,70 @@
+//! Round-trip tests against a corpus of `/col/metadata` responses.
+//!
+//! The fixtures mirror the console's compact JSON layout: field order, `isXvc`
+//! only present on items reporting it and empty strings instead of nulls. The
+//! server emulator serializes [`Metadata`] directly, so a byte-equivalent
+//! round-trip means it keeps that layout. Missing and unknown fields are
+//! covered by `metadata_leniency`.
+use std::path::{Path, PathBuf};
+
+use network_transfer::models::Metadata;
+
+fn fixtures() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/metadata");
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .expect("Fixture directory missing")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+
+    paths
+}
+
+fn load(name: &str) -> Metadata {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/metadata").join(name);
+    serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+}
+
+#[test]
+fn round_trip_byte_equivalent() {
+    let fixtures = fixtures();
+    assert!(fixtures.len() >= 4, "Fixture corpus incomplete: {fixtures:?}");
+
+    for path in fixtures {
+        let original = std::fs::read_to_string(&path).unwrap();
+        let metadata: Metadata = serde_json::from_str(&original)
+            .unwrap_or_else(|e| panic!("Failed deserializing {path:?}: {e}"));
+        assert!(!metadata.items.is_empty(), "{path:?}");
+        assert!(metadata.items.iter().all(|item| item.extra.is_empty()), "Unknown fields in {path:?}");
+
+        // Compared as strings, so field order counts
+        let serialized = serde_json::to_string(&metadata).unwrap();
+        assert_eq!(serialized, original, "Round-trip of {path:?} differs");
+    }
+}
+
+#[test]
+fn is_xvc_only_serialized_when_present() {
+    let xvc = load("xvc.json");
+    assert_eq!(xvc.items[0].is_xvc, Some(true));
+    assert_eq!(xvc.items[1].is_xvc, Some(false));
+
+    let apps = load("apps.json");
+    assert!(apps.items.iter().all(|item| item.is_xvc.is_none()));
+    let serialized = serde_json::to_string(&apps).unwrap();
+    assert!(!serialized.contains("isXvc"));
+}
+
+#[test]
+fn dlc_related_media() {
+    let dlc = load("dlc.json");
+    let (game, addon): (Vec<_>, Vec<_>) = dlc.items.iter().partition(|item| item.typ == "game");
+    let (game, addon) = (game[0], addon[0]);
+
+    assert_eq!(addon.typ, "dlc");
+    assert_eq!(game.related_media, [addon.content_id.as_str()]);
+    assert_eq!(game.related_media_family_names, [addon.package_family_name.as_str()]);
+    assert_eq!(addon.allowed_product_id, game.product_id);
+    assert_eq!(addon.allowed_package_family_name, game.package_family_name);
+}
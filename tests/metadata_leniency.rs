@@ -0,0 +1,52 @@
+//! Lenient parsing of `/col/metadata` documents with fields missing or added.
+//!
+//! The fixtures are not console captures. They vary the item of the
+//! `models` unit test, dropping fields a firmware might leave out and adding
+//! ones it might introduce.
+use std::path::Path;
+
+use network_transfer::models::Metadata;
+use serde_json::Value;
+
+fn load(name: &str) -> Value {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/lenient").join(name);
+    serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+}
+
+#[test]
+fn missing_fields_default() {
+    let (metadata, skipped) = Metadata::from_value_lenient(load("missing_fields.json")).unwrap();
+    assert_eq!(metadata.items.len(), 3);
+
+    let (complete, sparse, minimal) = (&metadata.items[0], &metadata.items[1], &metadata.items[2]);
+    assert_eq!(sparse.path, complete.path);
+    assert!(!sparse.has_content_id);
+    assert!(sparse.related_media.is_empty());
+    assert_eq!(sparse.generation, "uwpgen9");
+    assert_eq!(minimal.package_family_name, complete.package_family_name);
+    assert_eq!((minimal.size, minimal.version.as_str(), minimal.typ.as_str()), (0, "", ""));
+
+    // Only the path is required, its item alone is skipped
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].index, 3);
+    assert!(skipped[0].error.contains("path"), "{}", skipped[0].error);
+    assert!(serde_json::from_value::<Metadata>(load("missing_fields.json")).is_err());
+}
+
+#[test]
+fn extra_fields_preserved() {
+    let original = load("extra_fields.json");
+    let metadata: Metadata = serde_json::from_value(original.clone()).unwrap();
+
+    let item = &metadata.items[0];
+    assert_eq!(item.is_xvc, Some(false));
+    assert_eq!(item.extra["installState"], "installed");
+    assert_eq!(item.extra["tags"], serde_json::json!(["lan"]));
+    assert!(metadata.items[1].extra.is_empty());
+    assert_eq!(metadata.extra["totalCount"], 2);
+
+    // Unknown fields are serialized again, `isXvc` only where it was present
+    let serialized = serde_json::to_value(&metadata).unwrap();
+    assert_eq!(serialized, original);
+    assert!(serialized["items"][1].get("isXvc").is_none());
+}
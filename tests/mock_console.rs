@@ -0,0 +1,294 @@
+//! End to end tests of [`Client`] against a [`MockConsole`].
+use network_transfer::{
+    access::AccessPolicy,
+    chunking::ChunkSizing,
+    error::Error,
+    models::MetadataItem,
+    queue::{download_job, Job, JobState},
+    test_util::MockConsole,
+    Client, ContentPath, ContractVersion, Range, SizeSource,
+};
+use uuid::Uuid;
+
+const LIBRARY: [(&str, usize); 2] = [("small.appx", 1000), ("large.xvc", 300_000)];
+
+fn status(result: Result<impl std::fmt::Debug, Error>) -> u16 {
+    match result {
+        Err(Error::HttpError(e)) => match *e {
+            ureq::Error::Status(status, _) => status,
+            e => panic!("Unexpected transport error: {e}"),
+        },
+        other => panic!("Expected HTTP status error, got {other:?}"),
+    }
+}
+
+#[test]
+fn get_metadata_lists_library() {
+    let console = MockConsole::start(&LIBRARY).unwrap();
+    let (metadata, version) = console.client().get_metadata_versioned().unwrap();
+
+    assert_eq!(version, Some(ContractVersion::V1));
+    assert_eq!(metadata.items.len(), 2);
+    for (item, (name, size)) in metadata.items.iter().zip(LIBRARY) {
+        assert_eq!(item.path, console.items().iter().find(|i| i.file.ends_with(name)).unwrap().item.path);
+        assert_eq!(item.size, size);
+    }
+}
+
+#[test]
+fn get_item_filesize_and_stat() {
+    let console = MockConsole::start(&LIBRARY).unwrap();
+    let client = console.client();
+
+    for push_item in console.items() {
+        assert_eq!(client.get_item_filesize(&push_item.item).unwrap(), push_item.item.size);
+        let stat = client.stat(&push_item.item).unwrap();
+        assert_eq!(stat.source, SizeSource::Head);
+        assert!(stat.accepts_ranges);
+        assert_eq!(stat.content_type.as_deref(), Some("application/octet-stream"));
+    }
+}
+
+#[test]
+fn download_chunks_full_content() {
+    let console = MockConsole::start(&LIBRARY).unwrap();
+    let client = console.client();
+    let item = &console.items()[1].item;
+
+    let mut fixed = vec![];
+    let written = client.download_chunks(item, item.size, &mut fixed, 0x10000).unwrap();
+    assert_eq!(written, item.size);
+    assert_eq!(fixed, console.content("large.xvc"));
+
+    let mut adaptive = vec![];
+    let mut chunk_sizes = vec![];
+    let summary = client.download_chunks_adaptive(item, 0, item.size, &mut adaptive, ChunkSizing { initial: 1024, min: 1024, max: 0x10000 }, |progress| {
+        chunk_sizes.push(progress.chunk_size);
+    }).unwrap();
+    assert_eq!(adaptive, fixed);
+    assert_eq!(chunk_sizes.iter().sum::<usize>(), item.size);
+
+    assert_eq!(summary.bytes, item.size);
+    assert_eq!(summary.chunks, chunk_sizes.len());
+    assert_eq!(summary.retries, 0);
+    assert_eq!(summary.item, "large");
+    assert_eq!(summary.console, console.addr().to_string());
+    assert!(summary.peak_throughput >= summary.average_throughput);
+}
+
+#[test]
+fn resume_partial_download() {
+    let console = MockConsole::start(&LIBRARY).unwrap();
+    let client = console.client();
+    let push_item = &console.items()[1];
+    let expected = console.content("large.xvc");
+
+    let destination = std::env::temp_dir().join(format!("{}-resume.xvc", std::process::id()));
+    std::fs::write(&destination, &expected[..12345]).unwrap();
+
+    let job = Job {
+        id: 0,
+        console_id: "X1".into(),
+        item_path: push_item.item.package_family_name.clone(),
+        destination: destination.clone(),
+        state: JobState::Active,
+        error: None,
+    };
+    download_job(&client, &job, ChunkSizing::default()).unwrap();
+
+    let downloaded = std::fs::read(&destination).unwrap();
+    std::fs::remove_file(&destination).unwrap();
+    assert_eq!(downloaded, expected);
+}
+
+#[test]
+fn error_paths() {
+    let console = MockConsole::start(&LIBRARY).unwrap();
+    let client = console.client();
+
+    // Unknown item
+    let mut missing = console.items()[0].item.clone();
+    missing.path = missing.path.replace("small.appx", "missing.appx");
+    missing.size = 0;
+    assert_eq!(status(client.download_chunks(&missing, 10, &mut vec![], 10)), 404);
+    assert_eq!(status(client.get_item_filesize(&missing)), 404);
+
+    // Resume offset beyond the content
+    let item = &console.items()[0].item;
+    assert!(client.download_chunks_from(item, item.size + 1, item.size, &mut vec![], 10).is_err());
+
+    // Unsatisfiable range
+    let range = network_transfer::Range::from_len(item.size, 10).unwrap();
+    assert_eq!(status(client.download_chunk(&item.path, &range)), 416);
+
+    // Unsupported contract version
+    let result = console.client().with_contract_version(ContractVersion(2)).get_metadata();
+    assert!(matches!(
+        result,
+        Err(Error::UnsupportedContractVersion { requested: ContractVersion(2), ref supported }) if supported == &[ContractVersion::V1]
+    ));
+
+    // Console gone
+    let addr = console.addr();
+    drop(console);
+    let result = Client::new(&addr.ip().to_string(), addr.port()).get_metadata();
+    assert!(matches!(result, Err(Error::HttpError(ref e)) if matches!(**e, ureq::Error::Transport(_))));
+}
+
+#[test]
+fn access_policy_denies_with_403() {
+    let denied = MockConsole::start_with(&LIBRARY, |builder| {
+        builder.access_policy(AccessPolicy::new().deny_address("127.0.0.0/8".parse().unwrap()))
+    }).unwrap();
+    let item = denied.items()[0].item.clone();
+    assert_eq!(status(denied.client().get_metadata()), 403);
+    assert_eq!(status(denied.client().download_chunk(&item.path, &Range::single(0))), 403);
+
+    let by_agent = MockConsole::start_with(&LIBRARY, |builder| {
+        builder.access_policy(AccessPolicy::new().allow_user_agent("SomeOtherAgent"))
+    }).unwrap();
+    assert_eq!(status(by_agent.client().get_metadata()), 403);
+
+    let allowed = MockConsole::start_with(&LIBRARY, |builder| {
+        builder.access_policy(AccessPolicy::new().allow_address("127.0.0.1".parse().unwrap()).allow_user_agent("CopyOnLanSvc"))
+    }).unwrap();
+    assert_eq!(allowed.client().get_metadata().unwrap().items.len(), 2);
+}
+
+#[test]
+fn metrics_count_requests_and_bytes() {
+    let console = MockConsole::start_with(&LIBRARY, |builder| {
+        builder.admin_address(Some("127.0.0.1:0".parse().unwrap()))
+    }).unwrap();
+    let client = console.client();
+    let item = &console.items()[0].item;
+
+    client.get_metadata().unwrap();
+    let mut content = vec![];
+    client.download_chunks(item, item.size, &mut content, 300).unwrap();
+    let mut missing = item.clone();
+    missing.path = missing.path.replace("small", "missing");
+    missing.size = 0;
+    assert_eq!(status(client.get_item_filesize(&missing)), 404);
+
+    let metrics = console.server().state().metrics();
+    assert_eq!(metrics.requests(200), 1);
+    assert_eq!(metrics.requests(206), 4);
+    // Stat tries a HEAD request, then a range probe
+    assert_eq!(metrics.requests(404), 2);
+    assert_eq!(metrics.bytes_served(&item.path), item.size as u64);
+    assert_eq!(metrics.active_transfers(), 0);
+
+    let admin = console.server().admin_addr().unwrap();
+    let rendered = ureq::get(&format!("http://{admin}/metrics")).call().unwrap().into_string().unwrap();
+    assert!(rendered.contains("network_transfer_requests_total{status=\"206\"} 4\n"), "{rendered}");
+    assert!(rendered.contains(&format!("network_transfer_bytes_served_total{{item=\"{}\"}} {}\n", item.path, item.size)), "{rendered}");
+}
+
+#[test]
+fn admin_api_manages_library_and_console() {
+    let console = MockConsole::start_with(&LIBRARY, |builder| {
+        builder.admin_address(Some("127.0.0.1:0".parse().unwrap()))
+    }).unwrap();
+    let admin = format!("http://{}", console.server().admin_addr().unwrap());
+    let client = console.client();
+
+    let items: Vec<serde_json::Value> = ureq::get(&format!("{admin}/items")).call().unwrap().into_json().unwrap();
+    assert_eq!(items.len(), 2);
+
+    // Files outside the library directory are refused
+    let outside = std::env::temp_dir().join(format!("admin-{}.appx", std::process::id()));
+    std::fs::write(&outside, b"outside").unwrap();
+    for file in [outside.clone(), console.dir().join("..").join(outside.file_name().unwrap())] {
+        match ureq::post(&format!("{admin}/items")).send_json(serde_json::json!({ "file": file })) {
+            Err(ureq::Error::Status(status, _)) => assert_eq!(status, 400),
+            other => panic!("Expected 400, got {other:?}"),
+        }
+    }
+    std::fs::remove_file(&outside).unwrap();
+
+    // Add a file, it is listed and served right away
+    let file = console.dir().join("added.appx");
+    std::fs::write(&file, b"added at runtime").unwrap();
+    let response = ureq::post(&format!("{admin}/items")).send_json(serde_json::json!({ "file": file })).unwrap();
+    assert_eq!(response.status(), 201);
+    let added: Vec<MetadataItem> = response.into_json().unwrap();
+    assert_eq!(client.get_metadata().unwrap().items.len(), 3);
+    let mut content = vec![];
+    client.download_chunks(&added[0], added[0].size, &mut content, 4).unwrap();
+    assert_eq!(content, b"added at runtime");
+
+    // Removed items are neither listed nor served
+    let removed = ureq::delete(&format!("{admin}/items")).query("path", &added[0].path).call().unwrap();
+    assert_eq!(removed.status(), 200);
+    assert_eq!(client.get_metadata().unwrap().items.len(), 2);
+    assert_eq!(status(client.download_chunk(&added[0].path, &Range::new(0, 0).unwrap())), 404);
+    // Not by name from the library directory either, whatever the drive id
+    let name = ContentPath::from_url_path(&added[0].path).unwrap().name;
+    let elsewhere = ContentPath::new(Uuid::new_v4(), &name).to_url_path();
+    assert_eq!(status(client.download_chunk(&elsewhere, &Range::new(0, 0).unwrap())), 404);
+    match ureq::delete(&format!("{admin}/items")).query("path", &added[0].path).call() {
+        Err(ureq::Error::Status(status, _)) => assert_eq!(status, 404),
+        other => panic!("Expected 404, got {other:?}"),
+    }
+
+    let transfers: Vec<serde_json::Value> = ureq::get(&format!("{admin}/transfers")).call().unwrap().into_json().unwrap();
+    assert!(transfers.is_empty());
+
+    let renamed: serde_json::Value = ureq::put(&format!("{admin}/console")).send_json(serde_json::json!({ "name": "LABBOX" })).unwrap().into_json().unwrap();
+    assert_eq!(renamed["name"], "LABBOX");
+    assert_eq!(console.server().console().name, "LABBOX");
+}
+
+#[test]
+fn admin_api_requires_token() {
+    // Reachable from the network only with a token
+    let exposed = MockConsole::start_with(&LIBRARY, |builder| builder.admin_address(Some("0.0.0.0:0".parse().unwrap())));
+    assert!(exposed.is_err());
+
+    let console = MockConsole::start_with(&LIBRARY, |builder| {
+        builder
+            .admin_address(Some("127.0.0.1:0".parse().unwrap()))
+            .admin_token(Some("secret".into()))
+    }).unwrap();
+    let console_url = format!("http://{}/console", console.server().admin_addr().unwrap());
+
+    for authorization in [None, Some("Bearer wrong"), Some("secret")] {
+        let request = ureq::get(&console_url);
+        let request = match authorization {
+            Some(authorization) => request.set("Authorization", authorization),
+            None => request,
+        };
+        match request.call() {
+            Err(ureq::Error::Status(status, response)) => {
+                assert_eq!(status, 401);
+                assert_eq!(response.header("www-authenticate"), Some("Bearer"));
+            }
+            other => panic!("Expected 401, got {other:?}"),
+        }
+    }
+
+    let response = ureq::get(&console_url).set("Authorization", "Bearer secret").call().unwrap();
+    assert_eq!(response.status(), 200);
+}
+
+#[test]
+fn shutdown_drains_in_flight_responses() {
+    let console = MockConsole::start_with(&LIBRARY, |builder| {
+        builder.rate_limit(Some(100_000)).drain_timeout(std::time::Duration::from_secs(10))
+    }).unwrap();
+    let item = console.items()[1].item.clone();
+    let client = console.client();
+
+    // A single slow range, still in flight when the server shuts down
+    let downloading = std::thread::spawn(move || {
+        let mut content = vec![];
+        client.download_chunks(&item, item.size, &mut content, item.size).map(|_| content)
+    });
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    console.server().shutdown();
+
+    assert_eq!(downloading.join().unwrap().unwrap(), console.content("large.xvc"));
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    assert!(matches!(console.client().get_metadata(), Err(Error::HttpError(_))));
+}
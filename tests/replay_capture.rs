@@ -0,0 +1,131 @@
+//! [`Client`] against recorded responses served by [`replay::router`].
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use network_transfer::{
+    capture::{self, CapturedRequest, CapturedResponse, Exchange, DEFAULT_MAX_BODY},
+    error::Error,
+    models::MetadataItem,
+    replay::{self, ReplayIndex},
+    Client, SizeSource,
+};
+
+const CAPTURE: &str = "tests/fixtures/captures/session.jsonl";
+const PATH: &str = "/col/content/%7B5E3E3C4A-6E2B-4C1F-9D0A-0F6C2B7E8A11%7D%23Contoso.Sample_8wekyb3d8bbwe";
+
+/// Serve `exchanges` on an ephemeral localhost port, for the lifetime of the test process
+fn replay(exchanges: Vec<Exchange>) -> Client {
+    let app = replay::router(ReplayIndex::new(exchanges));
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .unwrap();
+    let server = {
+        // Binding needs the runtime's reactor
+        let _guard = runtime.enter();
+        axum::Server::bind(&SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).serve(app.into_make_service())
+    };
+    let addr = server.local_addr();
+    std::thread::spawn(move || runtime.block_on(server));
+
+    Client::new(&addr.ip().to_string(), addr.port())
+}
+
+fn exchange(request: &str, response: &str) -> Exchange {
+    Exchange::new(
+        None,
+        UNIX_EPOCH,
+        Duration::ZERO,
+        CapturedRequest::parse(request.as_bytes(), DEFAULT_MAX_BODY).unwrap(),
+        CapturedResponse::parse(response.as_bytes(), DEFAULT_MAX_BODY).unwrap(),
+    )
+}
+
+fn head() -> Exchange {
+    exchange(
+        &format!("HEAD {PATH} HTTP/1.1\r\n\r\n"),
+        "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: 16\r\n\r\n",
+    )
+}
+
+fn probe(status: &str, headers: &str, body: &str) -> Exchange {
+    exchange(
+        &format!("GET {PATH} HTTP/1.1\r\nRange: bytes=0-0\r\n\r\n"),
+        &format!("HTTP/1.1 {status}\r\nContent-Type: application/octet-stream\r\n{headers}Content-Length: {}\r\n\r\n{body}", body.len()),
+    )
+}
+
+fn item(size: usize) -> MetadataItem {
+    serde_json::from_value(serde_json::json!({ "packageFamilyName": "Contoso.Sample_8wekyb3d8bbwe", "size": size, "path": PATH })).unwrap()
+}
+
+#[test]
+fn client_runs_against_capture() {
+    let client = replay(capture::load(Path::new(env!("CARGO_MANIFEST_DIR")).join(CAPTURE)).unwrap());
+
+    let metadata = client.get_metadata().unwrap();
+    assert_eq!(metadata.items.len(), 1);
+    let item = &metadata.items[0];
+    assert_eq!(item.package_family_name, "Contoso.Sample_8wekyb3d8bbwe");
+    assert_eq!(item.size, 16);
+
+    // The capture holds the two 8 byte ranges the client requests
+    let mut content = vec![];
+    let written = client.download_chunks(item, item.size, &mut content, 8).unwrap();
+    assert_eq!(written, 16);
+    assert_eq!(content, (0x40..0x50).collect::<Vec<u8>>());
+
+    // Ranges that were not recorded are not served
+    let unrecorded = network_transfer::Range::new(0, 15).unwrap();
+    assert!(client.download_chunk(&item.path, &unrecorded).is_err());
+}
+
+#[test]
+fn download_fails_when_item_changes() {
+    let chunk = |range: &str, response: &str| exchange(&format!("GET {PATH} HTTP/1.1\r\nRange: bytes={range}\r\n\r\n"), response);
+    let client = replay(vec![
+        chunk("0-7", "HTTP/1.1 206 OK\r\nContent-Range: bytes 0-7/16\r\nETag: \"v1\"\r\nContent-Length: 8\r\n\r\n@ABCDEFG"),
+        // What a server sends for a stale If-Range, all of the new version
+        chunk("8-15", "HTTP/1.1 200 OK\r\nETag: \"v2\"\r\nContent-Length: 16\r\n\r\n0123456789abcdef"),
+    ]);
+
+    match client.download_chunks(&item(16), 16, &mut vec![], 8) {
+        Err(Error::GeneralError(e)) => assert!(e.contains("changed during download"), "{e}"),
+        other => panic!("Expected change to be detected, got {other:?}"),
+    }
+}
+
+#[test]
+fn stat_combines_head_and_range_probe() {
+    // No accept-ranges on HEAD, like a console, the probe shows ranges work
+    let stat = replay(vec![head(), probe("206 OK", "Content-Range: bytes 0-0/16\r\n", "@")]).stat(&item(0)).unwrap();
+    assert_eq!((stat.size, stat.source, stat.accepts_ranges), (16, SizeSource::Head, true));
+    assert_eq!(stat.content_type.as_deref(), Some("application/octet-stream"));
+
+    // Range ignored, the full content comes back
+    let stat = replay(vec![head(), probe("200 OK", "", "@ABCDEFGHIJKLMNO")]).stat(&item(0)).unwrap();
+    assert_eq!((stat.size, stat.source, stat.accepts_ranges), (16, SizeSource::Head, false));
+
+    // HEAD rejected
+    let stat = replay(vec![probe("206 OK", "Content-Range: bytes 0-0/16\r\n", "@")]).stat(&item(0)).unwrap();
+    assert_eq!((stat.size, stat.source, stat.accepts_ranges), (16, SizeSource::RangeProbe, true));
+}
+
+#[test]
+fn stat_falls_back_to_metadata_size() {
+    // Both requests rejected
+    let client = replay(vec![]);
+    let stat = client.stat(&item(16)).unwrap();
+    assert_eq!((stat.size, stat.source, stat.accepts_ranges), (16, SizeSource::Metadata, false));
+
+    // Without a metadata size the probe's error is returned
+    match client.stat(&item(0)) {
+        Err(Error::HttpError(e)) => assert!(matches!(*e, ureq::Error::Status(404, _))),
+        other => panic!("Expected 404, got {other:?}"),
+    }
+}